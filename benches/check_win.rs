@@ -0,0 +1,76 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use maj_spirit::game::{Cards, check_win};
+
+fn random_hands(n: usize) -> Vec<Cards> {
+    let mut rng = StdRng::seed_from_u64(20251010);
+    let mut stack = [0; 136];
+    for (i, card) in stack.iter_mut().enumerate() {
+        *card = (i / 4) as u8;
+    }
+    let mut hands = Vec::with_capacity(n);
+    for _ in 0..n {
+        stack.shuffle(&mut rng);
+        let mut cards = Cards::default();
+        for &card in stack.iter().take(14) {
+            cards.insert(card);
+        }
+        hands.push(cards);
+    }
+    return hands;
+}
+
+fn winning_hands() -> Vec<Cards> {
+    let mut hands = Vec::new();
+    // 123m 456m 789m 111p 99s
+    let mut cards = Cards::default();
+    for card in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 9, 9, 26, 26] {
+        cards.insert(card);
+    }
+    hands.push(cards);
+    // seven pairs
+    let mut cards = Cards::default();
+    for card in [0, 0, 4, 4, 10, 10, 15, 15, 20, 20, 27, 27, 33, 33] {
+        cards.insert(card);
+    }
+    hands.push(cards);
+    // 111222333m 444p 55s
+    let mut cards = Cards::default();
+    for card in [0, 0, 0, 1, 1, 1, 2, 2, 2, 12, 12, 12, 22, 22] {
+        cards.insert(card);
+    }
+    hands.push(cards);
+    return hands;
+}
+
+fn bench_check_win(c: &mut Criterion) {
+    let hands = random_hands(1000);
+    let wins = winning_hands();
+
+    // build the tables outside of the measurement
+    check_win::table::check(&hands[0]);
+
+    let mut group = c.benchmark_group("check_win/random");
+    group.bench_function("dp", |b| {
+        b.iter(|| hands.iter().filter(|h| check_win::dp::check(h)).count())
+    });
+    group.bench_function("table", |b| {
+        b.iter(|| hands.iter().filter(|h| check_win::table::check(h)).count())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("check_win/winning");
+    group.bench_function("dp", |b| {
+        b.iter(|| wins.iter().filter(|h| check_win::dp::check(h)).count())
+    });
+    group.bench_function("table", |b| {
+        b.iter(|| wins.iter().filter(|h| check_win::table::check(h)).count())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_check_win);
+criterion_main!(benches);
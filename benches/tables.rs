@@ -0,0 +1,217 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::extract::{Extension, Form, Path, State};
+use axum::routing::any;
+use axum::{Router, middleware};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use deadpool_sqlite::{Config, Pool, Runtime};
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::RwLock;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{Message, client::IntoClientRequest};
+
+use maj_spirit::config::{GAME_QUEUE_CAPACITY, PROTOCOL_VERSION};
+use maj_spirit::game::{Cards, GameMessage};
+use maj_spirit::jwt::get_token;
+use maj_spirit::state::AppState;
+use maj_spirit::txmanager::{FullPolicy, TxManager};
+use maj_spirit::ws::{ClientMessage, ServerMessage};
+use maj_spirit::{handle_room_join, handle_room_ready, handle_ws, init_db, jwt_auth};
+
+// messages each player sends in the dispatch benchmark
+const DISPATCH_MESSAGES: usize = 100;
+
+async fn db_pool() -> Arc<Pool> {
+    let path = std::env::temp_dir().join(format!("maj_spirit_bench_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db_pool = Config::new(path).create_pool(Runtime::Tokio1).unwrap();
+    init_db(&db_pool).await.unwrap();
+    return Arc::new(db_pool);
+}
+
+// connects and greets as `uid`, the returned future then discards the first
+// card it holds until the game ends
+async fn connect_player(addr: std::net::SocketAddr, uid: u64) -> impl Future<Output = ()> {
+    let mut req = format!("ws://{}/ws", addr).into_client_request().unwrap();
+    let token = format!("Bearer {}", get_token(uid).unwrap());
+    req.headers_mut()
+        .insert("Authorization", token.parse().unwrap());
+    let (mut ws, _) = connect_async(req).await.unwrap();
+    let hello = ClientMessage::Hello {
+        version: PROTOCOL_VERSION,
+        capabilities: Vec::new(),
+        encodings: Vec::new(),
+        last_seq: None,
+    };
+    let hello = serde_json::to_string(&hello).unwrap();
+    ws.send(Message::Text(hello.into())).await.unwrap();
+    // wait for `Welcome` so the socket is registered before the game starts
+    while let Some(Ok(msg)) = ws.next().await {
+        if let Message::Text(_) = msg {
+            break;
+        }
+    }
+
+    return async move {
+        let mut cards = Cards::default();
+        while let Some(Ok(msg)) = ws.next().await {
+            let Message::Text(json_text) = msg else {
+                continue;
+            };
+            let msg = match serde_json::from_str(&json_text).unwrap() {
+                ServerMessage::Sequenced { msg, .. } => *msg,
+                msg => msg,
+            };
+            match msg {
+                ServerMessage::CardSync(new_cards) => cards = new_cards,
+                ServerMessage::GetCard(card) => cards.insert(card),
+                ServerMessage::GameEnd(_) => return,
+                _ => continue,
+            }
+            if cards.iter().map(|&x| x as usize).sum::<usize>() % 3 != 2 {
+                continue;
+            }
+            let card = cards.iter().position(|&x| x > 0).unwrap() as u8;
+            cards.delete(card);
+            let msg = serde_json::to_string(&ClientMessage::Discard(card)).unwrap();
+            if ws.send(Message::Text(msg.into())).await.is_err() {
+                return;
+            }
+        }
+    };
+}
+
+// seats four sockets per table through the room handlers and plays a game
+// at every table, all discards go through `handle_socket` and the routes
+async fn play_sockets(db_pool: Arc<Pool>, tables: u64) {
+    let state = AppState::new(db_pool);
+    let app = Router::new()
+        .route("/ws", any(handle_ws))
+        .route_layer(middleware::from_fn(jwt_auth))
+        .with_state(state.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let mut players = Vec::new();
+    for table in 0..tables {
+        let room_id = table as usize + 1;
+        let uids = [0, 1, 2, 3].map(|seat| table * 4 + seat + 1);
+        for uid in uids {
+            players.push(tokio::spawn(connect_player(addr, uid).await));
+            let params = Ok(Form(serde_json::from_str("{}").unwrap()));
+            handle_room_join(Path(room_id), State(state.clone()), Extension(uid), params).await;
+        }
+        // the last one ready starts the game
+        for uid in uids {
+            handle_room_ready(Path(room_id), State(state.clone()), Extension(uid)).await;
+        }
+    }
+    for player in players {
+        player.await.unwrap();
+    }
+    server.abort();
+}
+
+// keeps taking the hall for writing, as room operations do
+fn spawn_churn(state: &AppState, stop: Arc<AtomicBool>) -> tokio::task::JoinHandle<()> {
+    let hall = state.hall.clone();
+    return tokio::spawn(async move {
+        while !stop.load(Ordering::Relaxed) {
+            let hall = hall.write().await;
+            tokio::task::yield_now().await;
+            drop(hall);
+            tokio::task::yield_now().await;
+        }
+    });
+}
+
+// sends `DISPATCH_MESSAGES` per player to the game of their table, either by
+// route or the way sockets did before: the hall for the room, then the
+// registry of games behind its own lock
+async fn dispatch(db_pool: Arc<Pool>, tables: usize, by_route: bool) {
+    let state = AppState::new(db_pool);
+    let games = RwLock::new(TxManager::new(GAME_QUEUE_CAPACITY, FullPolicy::Reject));
+    let games = Arc::new(games);
+
+    let mut receivers = Vec::new();
+    {
+        let mut hall = state.hall.write().await;
+        for room_id in 0..tables {
+            let (tx, mut rx) = state.tx2games.channel();
+            for seat in 0..4 {
+                let uid = (room_id * 4 + seat) as u64;
+                hall.belongs.insert(uid, room_id);
+                state.routes.replace(uid, tx.clone());
+            }
+            games.write().await.insert(room_id, tx);
+            receivers.push(tokio::spawn(async move {
+                for _ in 0..4 * DISPATCH_MESSAGES {
+                    rx.recv().await.unwrap();
+                }
+            }));
+        }
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let churn = spawn_churn(&state, stop.clone());
+    let mut senders = Vec::new();
+    for uid in 0..(tables * 4) as u64 {
+        let state = state.clone();
+        let games = games.clone();
+        senders.push(tokio::spawn(async move {
+            for _ in 0..DISPATCH_MESSAGES {
+                let msg = GameMessage::Client((uid, ClientMessage::RequestCardSync.into()));
+                // the queues hold every message, neither path is rejected
+                if by_route {
+                    state.routes.send(&uid, msg).unwrap();
+                } else {
+                    let hall = state.hall.read().await;
+                    let room_id = hall.belongs[&uid];
+                    games.read().await.send(&room_id, msg).unwrap();
+                }
+            }
+        }));
+    }
+    for sender in senders {
+        sender.await.unwrap();
+    }
+    for receiver in receivers {
+        receiver.await.unwrap();
+    }
+    stop.store(true, Ordering::Relaxed);
+    churn.await.unwrap();
+}
+
+fn bench_tables(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let db_pool = runtime.block_on(db_pool());
+
+    let mut group = c.benchmark_group("sockets");
+    group.sample_size(10);
+    for tables in [50, 100] {
+        group.bench_with_input(BenchmarkId::from_parameter(tables), &tables, |b, &n| {
+            b.iter(|| runtime.block_on(play_sockets(db_pool.clone(), n)));
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("dispatch");
+    group.sample_size(10);
+    for tables in [100, 300] {
+        group.bench_with_input(BenchmarkId::new("routes", tables), &tables, |b, &n| {
+            b.iter(|| runtime.block_on(dispatch(db_pool.clone(), n, true)));
+        });
+        group.bench_with_input(BenchmarkId::new("hall_lock", tables), &tables, |b, &n| {
+            b.iter(|| runtime.block_on(dispatch(db_pool.clone(), n, false)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tables);
+criterion_main!(benches);
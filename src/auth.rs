@@ -7,7 +7,7 @@ use deadpool_sqlite::Pool;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
 
-use crate::config::PASSWORD_SALT;
+use crate::config::{BOT_NAME_PREFIX, PASSWORD_SALT};
 use crate::db::{add_user, verify_passhash};
 use crate::error::AppError;
 use crate::jwt;
@@ -19,15 +19,22 @@ pub struct User {
     password: String,
 }
 
+pub(crate) fn hash_password(password: &str) -> String {
+    let password_salted = password.to_string() + PASSWORD_SALT;
+    return hex::encode(Sha256::digest(&password_salted));
+}
+
 async fn register(db_pool: &Pool, user: User) -> Result<(), AppError> {
-    let password_salted = user.password + PASSWORD_SALT;
-    let passhash = hex::encode(Sha256::digest(&password_salted));
+    // reserved for bot accounts
+    if user.username.starts_with(BOT_NAME_PREFIX) {
+        return Err(AppError::UserAlreadyExist);
+    }
+    let passhash = hash_password(&user.password);
     return add_user(db_pool, &user.username, &passhash).await;
 }
 
 async fn login(db_pool: &Pool, user: User) -> Result<String, AppError> {
-    let password_salted = user.password + PASSWORD_SALT;
-    let passhash = hex::encode(Sha256::digest(&password_salted));
+    let passhash = hash_password(&user.password);
     let uid = verify_passhash(db_pool, &user.username, &passhash).await?;
     return jwt::get_token(uid);
 }
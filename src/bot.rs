@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::game::{Cards, GameMessage};
+use crate::state::AppState;
+use crate::txmanager::Rx;
+use crate::ws::{ClientMessage, ErrorCode, ServerMessage};
+
+pub trait Strategy: Send + Sync {
+    /// Chooses a card to discard from a hand that just drew.
+    fn discard(&self, cards: &Cards) -> u8;
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StrategyKind {
+    #[default]
+    Shanten,
+    Min,
+}
+
+impl StrategyKind {
+    pub fn build(self) -> Arc<dyn Strategy> {
+        match self {
+            StrategyKind::Shanten => return Arc::new(ShantenStrategy),
+            StrategyKind::Min => return Arc::new(MinStrategy),
+        }
+    }
+}
+
+/// Discards the smallest card, same as the client's auto mode.
+pub struct MinStrategy;
+
+impl Strategy for MinStrategy {
+    fn discard(&self, cards: &Cards) -> u8 {
+        return cards.iter().position(|&x| x > 0).unwrap() as u8;
+    }
+}
+
+/// Discards the card leaving the lowest shanten, then the most tiles that improve it.
+pub struct ShantenStrategy;
+
+impl Strategy for ShantenStrategy {
+    fn discard(&self, cards: &Cards) -> u8 {
+        let mut cards = *cards;
+        let mut best = None;
+        for card in 0..34 {
+            if cards[card] == 0 {
+                continue;
+            }
+            cards[card] -= 1;
+            let current = shanten(&cards);
+            let mut waits = 0;
+            for draw in 0..34 {
+                if cards[draw] >= 4 {
+                    continue;
+                }
+                cards[draw] += 1;
+                if shanten(&cards) < current {
+                    waits += 4 - cards[draw] as i32 + 1;
+                }
+                cards[draw] -= 1;
+            }
+            cards[card] += 1;
+
+            // prefer honors and terminals on ties, they are the hardest to use
+            let isolated = card >= 27 || card % 9 == 0 || card % 9 == 8;
+            let key = (current, -waits, !isolated);
+            if best.is_none_or(|(best_key, _)| key < best_key) {
+                best = Some((key, card));
+            }
+        }
+        return best.unwrap().1 as u8;
+    }
+}
+
+fn search(cards: &mut Cards, mut i: usize, melds: i32, partials: i32, head: bool, best: &mut i32) {
+    while i < 34 && cards[i] == 0 {
+        i += 1;
+    }
+    if i == 34 {
+        let partials = partials.min(4 - melds);
+        *best = (*best).min(8 - 2 * melds - partials - head as i32);
+        return;
+    }
+
+    let suited = i < 27;
+    if cards[i] >= 3 {
+        cards[i] -= 3;
+        search(cards, i, melds + 1, partials, head, best);
+        cards[i] += 3;
+    }
+    if suited && i % 9 <= 6 && cards[i + 1] > 0 && cards[i + 2] > 0 {
+        cards[i] -= 1;
+        cards[i + 1] -= 1;
+        cards[i + 2] -= 1;
+        search(cards, i, melds + 1, partials, head, best);
+        cards[i] += 1;
+        cards[i + 1] += 1;
+        cards[i + 2] += 1;
+    }
+    if cards[i] >= 2 {
+        cards[i] -= 2;
+        if !head {
+            search(cards, i, melds, partials, true, best);
+        }
+        search(cards, i, melds, partials + 1, head, best);
+        cards[i] += 2;
+    }
+    if suited && i % 9 <= 7 && cards[i + 1] > 0 {
+        cards[i] -= 1;
+        cards[i + 1] -= 1;
+        search(cards, i, melds, partials + 1, head, best);
+        cards[i] += 1;
+        cards[i + 1] += 1;
+    }
+    if suited && i % 9 <= 6 && cards[i + 2] > 0 {
+        cards[i] -= 1;
+        cards[i + 2] -= 1;
+        search(cards, i, melds, partials + 1, head, best);
+        cards[i] += 1;
+        cards[i + 2] += 1;
+    }
+    cards[i] -= 1;
+    search(cards, i, melds, partials, head, best);
+    cards[i] += 1;
+}
+
+/// Number of cards away from ready, -1 means the hand has already won.
+pub fn shanten(cards: &Cards) -> i32 {
+    let mut best = 8;
+    search(&mut cards.clone(), 0, 0, 0, false, &mut best);
+
+    // seven distinct pairs also win
+    let pairs = cards.iter().filter(|&&x| x >= 2).count() as i32;
+    let kinds = cards.iter().filter(|&&x| x >= 1).count() as i32;
+    let seven_pairs = 6 - pairs + (7 - kinds).max(0);
+
+    return best.min(seven_pairs);
+}
+
+pub async fn run_bot(
+    state: AppState,
+    room_id: usize,
+    uid: u64,
+    strategy: Arc<dyn Strategy>,
+    mut rx: Rx<ServerMessage>,
+) {
+    let send = async |msg: ClientMessage| {
+        let tx2games = &state.tx2games;
+        if let Err(e) = tx2games.send(&room_id, GameMessage::Client((uid, msg.into()))) {
+            tracing::error!("{:?}", e);
+        }
+    };
+    // searching a hand can take a while, keep it off the runtime workers
+    let discard = async |cards: Cards| {
+        let strategy = strategy.clone();
+        match tokio::task::spawn_blocking(move || strategy.discard(&cards)).await {
+            Ok(card) => send(ClientMessage::Discard(card)).await,
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    };
+
+    let mut cards = Cards::default();
+    while let Some(msg) = rx.recv().await {
+        let msg = match msg {
+            ServerMessage::Sequenced { msg, .. } => *msg,
+            msg => msg,
+        };
+        match msg {
+            ServerMessage::CardSync(new_cards) => {
+                cards = new_cards;
+                // the host starts with one more card
+                if cards.iter().map(|&x| x as usize).sum::<usize>() % 3 == 2 {
+                    discard(cards).await;
+                }
+            }
+            ServerMessage::GetCard(card) => {
+                cards.insert(card);
+                discard(cards).await;
+            }
+            ServerMessage::Discard((discard_uid, card)) if discard_uid == uid => {
+                cards.delete(card);
+            }
+            // a discard may have been rejected while paused
+            ServerMessage::Error {
+                code: ErrorCode::NotCurrentPlayer | ErrorCode::NotHaveCard,
+                ..
+            }
+            | ServerMessage::Resumed => {
+                send(ClientMessage::RequestCardSync).await;
+            }
+            _ => (),
+        }
+    }
+    tracing::debug!("bot {} left room {}", uid, room_id);
+    // an overflowed queue ends the bot while the game goes on, auto-play takes the seat
+    let tx2games = &state.tx2games;
+    match tx2games.send(&room_id, GameMessage::Disconnected(uid)) {
+        Err(AppError::TxNotExist) | Ok(_) => (),
+        Err(e) => tracing::error!("{:?}", e),
+    }
+}
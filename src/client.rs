@@ -1,9 +1,11 @@
-use std::{collections::HashMap, io::Write, sync::Arc, u8};
+use std::{collections::HashMap, io::Write, sync::Arc, time::Duration, u8};
 
 use futures_util::{SinkExt, StreamExt};
 use maj_spirit::{
+    config::{HEARTBEAT_INTERVAL, PROTOCOL_VERSION},
     game::Cards,
-    ws::{ClientMessage, ServerMessage},
+    room::RoomView,
+    ws::{ClientMessage, Encoding, ErrorCode, ServerMessage},
 };
 use nyquest::{BlockingClient, ClientBuilder, blocking::Request, body_form};
 use tokio::sync::{RwLock, mpsc};
@@ -27,6 +29,9 @@ pub enum ClientError {
 
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
+
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
 }
 
 fn read_line() -> Result<String, ClientError> {
@@ -106,6 +111,237 @@ fn get_username_cached<'a>(
     return Ok(cache.get(&uid).unwrap());
 }
 
+fn error_text(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::BadRequest => return "无法识别的请求",
+        ErrorCode::GameNotStart => return "游戏尚未启动",
+        ErrorCode::UserNotInRoom => return "你不在房间内",
+        ErrorCode::NotPlayer => return "你正在观战，不能操作",
+        ErrorCode::NotCurrentPlayer => return "你当前不能出牌",
+        ErrorCode::NotHaveCard => return "你没有足够的牌",
+        ErrorCode::GamePaused => return "游戏暂停中",
+        ErrorCode::GameNotPaused => return "游戏没有暂停",
+        ErrorCode::ChatTooLong => return "消息过长",
+        ErrorCode::ChatRateLimited => return "发言过于频繁，请稍后再试",
+        ErrorCode::RematchNotOpen => return "当前不能投票",
+        ErrorCode::ServerBusy => return "服务器繁忙，请稍后再试",
+        ErrorCode::InternalError => return "服务器内部错误",
+    }
+}
+
+fn print_room(room: &RoomView) {
+    let members: Vec<&str> = room.members.iter().map(|m| m.username.as_str()).collect();
+    let status = if room.playing {
+        "对局中"
+    } else {
+        "等待中"
+    };
+    println!(
+        "房间 {}：{}/4 人，{}，座位规则 {:?}，成员：{:?}",
+        room.room_id, room.occupancy, status, room.seating, members
+    );
+}
+
+fn list_rooms(client: &BlockingClient, base_url: &str, all: bool) -> Result<(), ClientError> {
+    let resp = client.request(Request::get(format!(
+        "{}/rooms?joinable={}",
+        base_url, !all
+    )))?;
+    if resp.status() != 200 {
+        return Err(ClientError::Server(resp.text()?));
+    }
+    let rooms: Vec<RoomView> = serde_json::from_str(&resp.text()?)?;
+    if rooms.len() == 0 {
+        println!("当前没有房间");
+    }
+    for room in rooms.iter() {
+        print_room(room);
+    }
+    return Ok(());
+}
+
+fn view_room(
+    client: &BlockingClient,
+    base_url: &str,
+    auth_header: &str,
+    room_id: &str,
+) -> Result<(), ClientError> {
+    let resp = client.request(
+        Request::get(format!("{}/room/{}", base_url, room_id))
+            .with_header("Authorization", auth_header.to_string()),
+    )?;
+    if resp.status() != 200 {
+        return Err(ClientError::Server(resp.text()?));
+    }
+    let room: RoomView = serde_json::from_str(&resp.text()?)?;
+    print_room(&room);
+    let username = |uid: u64| {
+        room.members
+            .iter()
+            .find(|m| m.uid == uid)
+            .map(|m| m.username.as_str())
+            .unwrap_or("?")
+    };
+    if let Some(seat_map) = room.seat_map {
+        println!("上局座位：{:?}", seat_map.map(username));
+        if let Some(seed) = room.seat_seed {
+            println!("随机种子：{}", seed);
+        }
+    }
+    println!("房主：{}", username(room.owner));
+    println!("已选座位：{:?}", room.seats.map(|seat| seat.map(username)));
+    if let Some(code) = room.invite_code {
+        println!("邀请码：{}", code);
+    }
+    return Ok(());
+}
+
+fn update_rules(
+    client: &BlockingClient,
+    base_url: &str,
+    auth_header: &str,
+    room_id: &str,
+    key: &str,
+    value: &str,
+) -> Result<(), ClientError> {
+    let req = Request::post(format!("{}/room/{}/rules", base_url, room_id))
+        .with_header("Authorization", auth_header.to_string())
+        .with_body(body_form! {
+            key.to_string() => value.to_string(),
+        });
+    let resp = client.request(req)?;
+    if resp.status() != 200 {
+        return Err(ClientError::Server(resp.text()?));
+    }
+    println!("规则已更新");
+    return Ok(());
+}
+
+fn url_encode(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{:02X}", b));
+        }
+    }
+    return res;
+}
+
+fn make_private(
+    client: &BlockingClient,
+    base_url: &str,
+    auth_header: &str,
+    room_id: &str,
+    password: &str,
+) -> Result<(), ClientError> {
+    let req = Request::post(format!("{}/room/{}/private", base_url, room_id))
+        .with_header("Authorization", auth_header.to_string())
+        .with_body(body_form! {
+            "password" => password.to_string(),
+        });
+    let resp = client.request(req)?;
+    if resp.status() != 200 {
+        return Err(ClientError::Server(resp.text()?));
+    }
+    let code = resp.text()?;
+    if code.len() != 0 {
+        println!("房间已设为私有，邀请码：{}", code);
+    } else {
+        println!("房间已设为私有，需要密码加入");
+    }
+    return Ok(());
+}
+
+fn room_post(
+    client: &BlockingClient,
+    base_url: &str,
+    auth_header: &str,
+    path: &str,
+) -> Result<(), ClientError> {
+    let req = Request::post(format!("{}/room/{}", base_url, path))
+        .with_header("Authorization", auth_header.to_string());
+    let resp = client.request(req)?;
+    let resp_debug = format!("{:?}", resp);
+    let resp_text = resp.text()?;
+    if resp_text.len() != 0 {
+        println!("{}", resp_text);
+    } else {
+        println!("{}", resp_debug);
+    }
+    return Ok(());
+}
+
+// like `room_post`, with one field in a form body so it stays out of the URL
+fn room_post_form(
+    client: &BlockingClient,
+    base_url: &str,
+    auth_header: &str,
+    path: &str,
+    key: &str,
+    value: &str,
+) -> Result<(), ClientError> {
+    let req = Request::post(format!("{}/room/{}", base_url, path))
+        .with_header("Authorization", auth_header.to_string())
+        .with_body(body_form! {
+            key.to_string() => value.to_string(),
+        });
+    let resp = client.request(req)?;
+    let resp_debug = format!("{:?}", resp);
+    let resp_text = resp.text()?;
+    if resp_text.len() != 0 {
+        println!("{}", resp_text);
+    } else {
+        println!("{}", resp_debug);
+    }
+    return Ok(());
+}
+
+fn tournament_command(
+    client: &BlockingClient,
+    base_url: &str,
+    auth_header: &str,
+    cmd: &[&str],
+) -> Result<(), ClientError> {
+    let req = match cmd {
+        ["tournament", "create", name, hanchan] | ["tournament", "create", name, hanchan, _] => {
+            let pairing = cmd.get(4).copied().unwrap_or("swiss");
+            Request::post(format!("{}/tournament", base_url))
+                .with_header("Authorization", auth_header.to_string())
+                .with_body(body_form! {
+                    "name" => name.to_string(),
+                    "hanchan" => hanchan.to_string(),
+                    "pairing" => pairing.to_string(),
+                })
+        }
+        ["tournament", action @ ("register" | "start"), id] => {
+            Request::post(format!("{}/tournament/{}/{}", base_url, id, action))
+                .with_header("Authorization", auth_header.to_string())
+        }
+        ["tournament", "view", id] => Request::get(format!("{}/tournament/{}", base_url, id)),
+        ["tournament", "standings", id] => {
+            Request::get(format!("{}/tournament/{}/standings", base_url, id))
+        }
+        _ => {
+            println!("不合法的命令");
+            return Ok(());
+        }
+    };
+    let resp = client.request(req)?;
+    if resp.status() != 200 {
+        return Err(ClientError::Server(resp.text()?));
+    }
+    let text = resp.text()?;
+    match cmd[1] {
+        "create" => println!("比赛已创建，id 是 {}", text),
+        "register" => println!("报名成功"),
+        "start" => println!("比赛已开始"),
+        _ => println!("{}", text),
+    }
+    return Ok(());
+}
+
 #[tokio::main]
 async fn main() {
     nyquest_preset::register();
@@ -128,19 +364,63 @@ async fn main() {
     let password = rpassword::read_password().unwrap();
 
     let token = login(&client, &base_url, &username, &password).unwrap();
+
+    prompt("请选择消息编码 json / msgpack / cbor，直接回车默认为 json：");
+    let encoding = match read_line().unwrap().trim() {
+        "msgpack" => Encoding::Msgpack,
+        "cbor" => Encoding::Cbor,
+        _ => Encoding::Json,
+    };
+
     let auth_header = format!("Bearer {}", token);
     let mut ws_request = ws_url.into_client_request().unwrap();
     ws_request
         .headers_mut()
         .insert("Authorization", auth_header.clone().parse().unwrap());
 
-    let (ws_stream, ws_resp) = connect_async(ws_request.clone()).await.unwrap();
+    let (mut ws_stream, ws_resp) = connect_async(ws_request.clone()).await.unwrap();
 
     if ws_resp.status().as_u16() > 299 {
         println!("WebSocket 连接失败");
         return;
     }
 
+    let hello = ClientMessage::Hello {
+        version: PROTOCOL_VERSION,
+        capabilities: Vec::new(),
+        encodings: vec![encoding],
+        last_seq: None,
+    };
+    let hello = serde_json::to_string(&hello).unwrap();
+    ws_stream.send(Message::Text(hello.into())).await.unwrap();
+    // the server may fall back to JSON
+    let encoding = loop {
+        let Some(Ok(msg)) = ws_stream.next().await else {
+            println!("WebSocket 握手失败");
+            return;
+        };
+        let Message::Text(json_text) = msg else {
+            continue;
+        };
+        match serde_json::from_str(&json_text) {
+            Ok(ServerMessage::Welcome { encoding, .. }) => break encoding,
+            Ok(ServerMessage::IncompatibleVersion {
+                version,
+                min_version,
+            }) => {
+                println!(
+                    "客户端协议版本 {} 不受支持，服务器支持版本 {} 至 {}，请更新客户端",
+                    PROTOCOL_VERSION, min_version, version
+                );
+                return;
+            }
+            _ => {
+                println!("WebSocket 握手失败");
+                return;
+            }
+        }
+    };
+
     println!("WebSocket 连接成功");
 
     let (mut tx, mut rx) = ws_stream.split();
@@ -148,122 +428,306 @@ async fn main() {
     let (send_tx, mut send_rx) = mpsc::unbounded_channel::<ClientMessage>();
 
     tokio::spawn(async move {
-        while let Some(msg) = send_rx.recv().await {
-            let msg = serde_json::to_string(&msg).unwrap();
-            tx.send(Message::Text(msg.into())).await.unwrap();
+        // keeps the connection alive while the user is idle
+        let mut heartbeat = tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL));
+        loop {
+            tokio::select! {
+                msg = send_rx.recv() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+                    let msg = match encoding {
+                        Encoding::Json => Message::Text(serde_json::to_string(&msg).unwrap().into()),
+                        _ => Message::Binary(encoding.encode(&msg).unwrap().into()),
+                    };
+                    tx.send(msg).await.unwrap();
+                }
+                _ = heartbeat.tick() => {
+                    if tx.send(Message::Ping(Default::default())).await.is_err() {
+                        println!("连接已断开");
+                        break;
+                    }
+                }
+            }
         }
     });
 
     let current_game_info = Arc::new(RwLock::new(None));
     let current_cards = Arc::new(RwLock::new(Cards::default()));
     let is_auto = Arc::new(RwLock::new(false));
+    let last_seq = Arc::new(RwLock::new(0));
 
     let base_url_ = base_url.clone();
     let send_tx_ = send_tx.clone();
     let current_game_info_ = current_game_info.clone();
     let currnet_cards_ = current_cards.clone();
     let is_auto_ = is_auto.clone();
+    let last_seq_ = last_seq.clone();
     tokio::spawn(async move {
         let base_url = base_url_;
         let send_tx = send_tx_;
         let current_game_info = current_game_info_;
         let current_cards = currnet_cards_;
         let is_auto = is_auto_;
+        let last_seq = last_seq_;
         let mut username_cache = HashMap::new();
         while let Some(msg) = rx.next().await {
-            if let Ok(Message::Text(json_text)) = msg {
-                let msg;
-                match serde_json::from_str(&json_text) {
-                    Ok(res) => msg = res,
-                    Err(e) => {
-                        println!("error while deserializing msg: {:?}", e);
-                        continue;
-                    }
+            // text frames are JSON whatever was negotiated
+            let decoded = match msg {
+                Ok(Message::Text(json_text)) => Encoding::Json.decode(json_text.as_bytes()),
+                Ok(Message::Binary(bytes)) => encoding.decode(&bytes),
+                _ => continue,
+            };
+            let msg = match decoded {
+                Ok(msg) => msg,
+                Err(e) => {
+                    println!("error while deserializing msg: {:?}", e);
+                    continue;
+                }
+            };
+            let msg = match msg {
+                ServerMessage::Sequenced { seq, msg } => {
+                    *last_seq.write().await = seq;
+                    *msg
+                }
+                msg => msg,
+            };
+            match msg {
+                // unwrapped above, never nested
+                ServerMessage::Sequenced { .. } => (),
+                // requests from this client carry no id
+                ServerMessage::Ack(_) => (),
+                ServerMessage::Error { code, .. } => {
+                    println!("{}", error_text(code));
                 }
-                match msg {
-                    ServerMessage::GameNotStart => {
-                        println!("游戏尚未启动");
-                    }
-                    ServerMessage::UserNotInRoom => {
-                        println!("你不在房间内");
-                    }
-                    ServerMessage::NotCurrentPlayer => {
-                        println!("你当前不能出牌");
-                    }
 
-                    ServerMessage::GetCard(card) => {
-                        println!("你获得了：{}", Cards::card_name(card));
-                        current_cards.write().await.insert(card);
-                        println!("你的牌是：{}", current_cards.read().await);
+                ServerMessage::GetCard(card) => {
+                    println!("你获得了：{}", Cards::card_name(card));
+                    current_cards.write().await.insert(card);
+                    println!("你的牌是：{}", current_cards.read().await);
 
-                        if *is_auto.read().await {
-                            let cards = current_cards.read().await;
-                            let c = cards.into_iter().position(|x| x > 0).unwrap();
-                            send_tx.send(ClientMessage::Discard(c as u8)).unwrap();
-                        }
+                    if *is_auto.read().await {
+                        let cards = current_cards.read().await;
+                        let c = cards.into_iter().position(|x| x > 0).unwrap();
+                        send_tx.send(ClientMessage::Discard(c as u8)).unwrap();
                     }
-                    ServerMessage::NotHaveCard => {
-                        println!("你没有足够的牌");
-                    }
-                    ServerMessage::Discard((uid, card)) => {
-                        let current_username =
-                            get_username_cached(&base_url, uid, &mut username_cache).unwrap();
-                        println!(
-                            "玩家 {} 打出了：{}",
-                            current_username,
-                            Cards::card_name(card)
-                        );
-                        if current_username == &username {
-                            current_cards.write().await.delete(card);
-                        }
+                }
+                ServerMessage::Discard((uid, card)) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!(
+                        "玩家 {} 打出了：{}",
+                        current_username,
+                        Cards::card_name(card)
+                    );
+                    if current_username == &username {
+                        current_cards.write().await.delete(card);
                     }
+                }
 
-                    ServerMessage::GameInfoSync(game_info) => {
-                        println!("同步游戏信息");
+                ServerMessage::GameInfoSync(game_info) => {
+                    println!("同步游戏信息");
+                    println!(
+                        "玩家：{:?}",
+                        game_info.players.map(|uid| get_username_cached(
+                            &base_url,
+                            uid,
+                            &mut username_cache
+                        )
+                        .unwrap()
+                        .to_string())
+                    );
+                    println!("分数：{:?}", game_info.players_score);
+                    *current_game_info.write().await = Some(game_info);
+                }
+                ServerMessage::TableSnapshot(snapshot) => {
+                    println!("同步牌桌信息");
+                    let game_info = snapshot.game_info;
+                    for (i, river) in snapshot.rivers.iter().enumerate() {
+                        let current_username = get_username_cached(
+                            &base_url,
+                            game_info.players[i],
+                            &mut username_cache,
+                        )
+                        .unwrap();
+                        let river: String =
+                            river.iter().map(|&card| Cards::card_name(card)).collect();
                         println!(
-                            "玩家：{:?}",
-                            game_info.players.map(|uid| get_username_cached(
-                                &base_url,
-                                uid,
-                                &mut username_cache
-                            )
-                            .unwrap()
-                            .to_string())
+                            "玩家 {}（{} 分）牌河：{}",
+                            current_username, game_info.players_score[i], river
                         );
-                        println!("分数：{:?}", game_info.players_score);
-                        *current_game_info.write().await = Some(game_info);
                     }
-                    ServerMessage::CardSync(cards) => {
-                        println!("同步手牌信息");
+                    let current_username = get_username_cached(
+                        &base_url,
+                        snapshot.current_player,
+                        &mut username_cache,
+                    )
+                    .unwrap();
+                    println!(
+                        "当前轮到 {}，牌山剩余 {} 张",
+                        current_username, snapshot.wall_remaining
+                    );
+                    if snapshot.paused {
+                        println!("游戏暂停中");
+                    }
+                    if let Some(cards) = snapshot.hand {
                         println!("你现在的手牌是：{}", cards);
                         *current_cards.write().await = cards;
                     }
+                    *current_game_info.write().await = Some(game_info);
+                }
+                ServerMessage::CardSync(cards) => {
+                    println!("同步手牌信息");
+                    println!("你现在的手牌是：{}", cards);
+                    *current_cards.write().await = cards;
+                }
 
-                    ServerMessage::RoundStart(round_id) => {
-                        println!("本轮开始，为本局的第 {} 轮", round_id);
-                    }
-                    ServerMessage::WinAll(uid) => {
+                ServerMessage::RoundStart(round_id) => {
+                    println!("本轮开始，为本局的第 {} 轮", round_id);
+                }
+                ServerMessage::WinAll(uid) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("玩家 {} 自摸", current_username);
+                }
+                ServerMessage::WinOne((win_uid, lose_uid)) => {
+                    let win_username = get_username_cached(&base_url, win_uid, &mut username_cache)
+                        .unwrap()
+                        .to_string();
+                    let lose_username =
+                        get_username_cached(&base_url, lose_uid, &mut username_cache)
+                            .unwrap()
+                            .to_string();
+                    println!("玩家 {} 荣和，倒霉蛋是 {}", win_username, lose_username);
+                }
+                ServerMessage::Tie => {
+                    println!("流局");
+                }
+
+                ServerMessage::GameEnd(game_id) => {
+                    println!("游戏结束，对局 id 是 {}", game_id);
+                }
+
+                ServerMessage::GameResult(result) => {
+                    println!("本局结果：");
+                    for i in 0..4 {
                         let current_username =
-                            get_username_cached(&base_url, uid, &mut username_cache).unwrap();
-                        println!("玩家 {} 自摸", current_username);
-                    }
-                    ServerMessage::WinOne((win_uid, lose_uid)) => {
-                        let win_username =
-                            get_username_cached(&base_url, win_uid, &mut username_cache)
-                                .unwrap()
-                                .to_string();
-                        let lose_username =
-                            get_username_cached(&base_url, lose_uid, &mut username_cache)
-                                .unwrap()
-                                .to_string();
-                        println!("玩家 {} 荣和，倒霉蛋是 {}", win_username, lose_username);
-                    }
-                    ServerMessage::Tie => {
-                        println!("流局");
+                            get_username_cached(&base_url, result.players[i], &mut username_cache)
+                                .unwrap();
+                        println!("玩家 {}：{}", current_username, result.players_score[i]);
                     }
+                    println!("输入 rematch yes 或 rematch no 投票是否再来一局");
+                }
+                ServerMessage::RematchVote((uid, agree)) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    let vote = if agree { "同意" } else { "拒绝" };
+                    println!("玩家 {} {}再来一局", current_username, vote);
+                }
+
+                ServerMessage::MemberJoined(uid) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("玩家 {} 加入了房间", current_username);
+                }
+                ServerMessage::MemberLeft(uid) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("玩家 {} 离开了房间", current_username);
+                }
+                ServerMessage::MemberKicked(uid) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("玩家 {} 被踢出了房间", current_username);
+                }
+                ServerMessage::RoomClosed(room_id) => {
+                    println!("房间 {} 已关闭", room_id);
+                }
+                ServerMessage::LobbyUpdate(room) => {
+                    println!(
+                        "大厅：房间 {}，{}/4 人，{:?}",
+                        room.room_id, room.occupancy, room.status
+                    );
+                }
+                ServerMessage::LobbyRemove(room_id) => {
+                    println!("大厅：房间 {} 已移除", room_id);
+                }
+
+                ServerMessage::TournamentTable {
+                    tournament_id,
+                    round,
+                    room_id,
+                } => {
+                    println!(
+                        "比赛 {} 第 {} 轮开始，你的桌号是房间 {}",
+                        tournament_id,
+                        round + 1,
+                        room_id
+                    );
+                }
+                ServerMessage::TournamentEnd(tournament_id) => {
+                    println!("比赛 {} 已结束", tournament_id);
+                }
 
-                    ServerMessage::GameEnd(game_id) => {
-                        println!("游戏结束，对局 id 是 {}", game_id);
+                ServerMessage::PauseVote((uid, pause)) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    let action = if pause { "暂停" } else { "继续" };
+                    println!("玩家 {} 请求{}游戏", current_username, action);
+                }
+                ServerMessage::PlayerDisconnected(uid) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("玩家 {} 断开连接，将自动摸切", current_username);
+                }
+                ServerMessage::PlayerReconnected(uid) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("玩家 {} 已重新连接", current_username);
+                }
+                ServerMessage::SessionReplaced => {
+                    println!("你的账号在别处登录，连接已断开");
+                }
+                // only expected during the handshake
+                ServerMessage::Welcome { .. } | ServerMessage::IncompatibleVersion { .. } => (),
+                ServerMessage::Paused => {
+                    println!("游戏已暂停");
+                }
+                ServerMessage::Resumed => {
+                    println!("游戏已继续");
+                    send_tx.send(ClientMessage::RequestCardSync).unwrap();
+                }
+
+                ServerMessage::ReadyState((uid, ready)) => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    let status = if ready { "已准备" } else { "取消准备" };
+                    println!("玩家 {} {}", current_username, status);
+                }
+
+                ServerMessage::MatchFound(room_id) => {
+                    println!("匹配成功，进入房间 {}", room_id);
+                }
+
+                ServerMessage::Chat { uid, text, ts: _ } => {
+                    let current_username =
+                        get_username_cached(&base_url, uid, &mut username_cache).unwrap();
+                    println!("[{}] {}", current_username, text);
+                }
+
+                ServerMessage::HandsReveal(players_cards) => {
+                    println!("本轮手牌公开：");
+                    let players = current_game_info.read().await.map(|info| info.players);
+                    for (i, cards) in players_cards.iter().enumerate() {
+                        match players {
+                            Some(players) => {
+                                let current_username =
+                                    get_username_cached(&base_url, players[i], &mut username_cache)
+                                        .unwrap();
+                                println!("玩家 {}：{}", current_username, cards);
+                            }
+                            None => println!("座位 {}：{}", i, cards),
+                        }
                     }
                 }
             }
@@ -290,23 +754,87 @@ async fn main() {
                     println!("不合法的命令");
                 } else {
                     match cmd[1] {
-                        "join" | "leave" | "start" => {
+                        "list" => {
+                            if cmd.len() > 3 || (cmd.len() == 3 && cmd[2] != "all") {
+                                println!("不合法的命令");
+                            } else if let Err(e) = list_rooms(&client, &base_url, cmd.len() == 3) {
+                                println!("错误：{}", e);
+                            }
+                        }
+                        "view" => {
+                            if cmd.len() != 3 {
+                                println!("不合法的命令");
+                            } else if let Err(e) =
+                                view_room(&client, &base_url, &auth_header, cmd[2])
+                            {
+                                println!("错误：{}", e);
+                            }
+                        }
+                        "join" | "spectate" if cmd.len() == 5 => {
+                            let key = match cmd[3] {
+                                "pw" | "password" => "password",
+                                "code" => "code",
+                                _ => {
+                                    println!("不合法的命令");
+                                    continue;
+                                }
+                            };
+                            let path = format!("{}/{}", cmd[2], cmd[1]);
+                            room_post_form(&client, &base_url, &auth_header, &path, key, cmd[4])
+                                .unwrap();
+                        }
+                        "private" => {
+                            if cmd.len() != 3 && cmd.len() != 4 {
+                                println!("不合法的命令");
+                            } else {
+                                let password = cmd.get(3).copied().unwrap_or("");
+                                if let Err(e) =
+                                    make_private(&client, &base_url, &auth_header, cmd[2], password)
+                                {
+                                    println!("错误：{}", e);
+                                }
+                            }
+                        }
+                        "rules" => {
+                            if cmd.len() != 5 {
+                                println!("不合法的命令");
+                            } else if let Err(e) = update_rules(
+                                &client,
+                                &base_url,
+                                &auth_header,
+                                cmd[2],
+                                cmd[3],
+                                cmd[4],
+                            ) {
+                                println!("错误：{}", e);
+                            }
+                        }
+                        "join" | "leave" | "start" | "ready" | "unready" | "public"
+                        | "spectate" => {
                             if cmd.len() != 3 {
                                 println!("不合法的命令");
                             } else {
-                                let req = Request::post(format!(
-                                    "{}/room/{}/{}",
-                                    base_url, cmd[2], cmd[1]
-                                ))
-                                .with_header("Authorization", auth_header.clone());
-                                let resp = client.request(req).unwrap();
-                                let resp_debug = format!("{:?}", resp);
-                                let resp_text = resp.text().unwrap();
-                                if resp_text.len() != 0 {
-                                    println!("{}", resp_text);
-                                } else {
-                                    println!("{}", resp_debug);
+                                let path = format!("{}/{}", cmd[2], cmd[1]);
+                                room_post(&client, &base_url, &auth_header, &path).unwrap();
+                            }
+                        }
+                        "bot" => {
+                            if cmd.len() != 3 && cmd.len() != 4 {
+                                println!("不合法的命令");
+                            } else {
+                                let mut path = format!("{}/bot", cmd[2]);
+                                if let Some(strategy) = cmd.get(3) {
+                                    path = format!("{}?strategy={}", path, url_encode(strategy));
                                 }
+                                room_post(&client, &base_url, &auth_header, &path).unwrap();
+                            }
+                        }
+                        "seat" | "seating" | "kick" => {
+                            if cmd.len() != 4 {
+                                println!("不合法的命令");
+                            } else {
+                                let path = format!("{}/{}/{}", cmd[2], cmd[1], cmd[3]);
+                                room_post(&client, &base_url, &auth_header, &path).unwrap();
                             }
                         }
                         _ => {
@@ -315,6 +843,32 @@ async fn main() {
                     }
                 }
             }
+            "tournament" => {
+                if let Err(e) = tournament_command(&client, &base_url, &auth_header, &cmd) {
+                    println!("错误：{}", e);
+                }
+            }
+            "queue" => {
+                if cmd.len() != 2 || (cmd[1] != "join" && cmd[1] != "leave") {
+                    println!("不合法的命令");
+                } else {
+                    let req = Request::post(format!("{}/queue/{}", base_url, cmd[1]))
+                        .with_header("Authorization", auth_header.clone());
+                    let resp = client.request(req).unwrap();
+                    if resp.status() == 200 {
+                        println!(
+                            "{}",
+                            if cmd[1] == "join" {
+                                "开始匹配"
+                            } else {
+                                "已取消匹配"
+                            }
+                        );
+                    } else {
+                        println!("{}", resp.text().unwrap());
+                    }
+                }
+            }
             "d" | "discard" => {
                 if cmd.len() != 2 {
                     println!("不合法的命令");
@@ -325,6 +879,50 @@ async fn main() {
                     }
                 }
             }
+            "replay" => {
+                if cmd.len() != 1 {
+                    println!("不合法的命令");
+                } else {
+                    let last_seq = *last_seq.read().await;
+                    send_tx.send(ClientMessage::Resume { last_seq }).unwrap();
+                }
+            }
+            "pause" | "resume" => {
+                if cmd.len() != 1 {
+                    println!("不合法的命令");
+                } else if cmd[0] == "pause" {
+                    send_tx.send(ClientMessage::RequestPause).unwrap();
+                } else {
+                    send_tx.send(ClientMessage::RequestResume).unwrap();
+                }
+            }
+            "rematch" => {
+                if cmd.len() != 2 || (cmd[1] != "yes" && cmd[1] != "no") {
+                    println!("不合法的命令");
+                } else {
+                    send_tx
+                        .send(ClientMessage::RematchVote(cmd[1] == "yes"))
+                        .unwrap();
+                }
+            }
+            "lobby" => {
+                if cmd.len() != 2 || (cmd[1] != "on" && cmd[1] != "off") {
+                    println!("不合法的命令");
+                } else if cmd[1] == "on" {
+                    send_tx.send(ClientMessage::SubscribeLobby).unwrap();
+                } else {
+                    send_tx.send(ClientMessage::UnsubscribeLobby).unwrap();
+                }
+            }
+            "say" => {
+                if cmd.len() < 2 {
+                    println!("不合法的命令");
+                } else {
+                    send_tx
+                        .send(ClientMessage::Chat(cmd[1..].join(" ")))
+                        .unwrap();
+                }
+            }
             "auto" => {
                 if cmd.len() != 1 {
                     println!("不合法的命令");
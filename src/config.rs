@@ -3,3 +3,30 @@ pub const DATABASE_FILE: &'static str = "maj_spirit.db";
 pub const JWT_SECRET: &'static [u8] = b"your_jwt_secret";
 pub const JWT_EXPIRE_DURATION: u64 = 2 * 60 * 60;
 pub const PASSWORD_SALT: &'static str = "your_password_salt";
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const RATING_K: f64 = 32.0;
+pub const MATCH_INTERVAL: u64 = 1;
+pub const MATCH_BASE_TOLERANCE: f64 = 50.0;
+pub const MATCH_TOLERANCE_GROWTH: f64 = 10.0;
+pub const MATCH_ROOM_BASE: usize = 1_000_000;
+pub const BOT_NAME_PREFIX: &'static str = "bot-";
+pub const CHAT_MAX_LEN: usize = 200;
+pub const CHAT_RATE_LIMIT: usize = 5;
+pub const CHAT_RATE_WINDOW: u64 = 10;
+pub const REMATCH_TIMEOUT: u64 = 30;
+pub const TOURNAMENT_ROOM_BASE: usize = 2_000_000;
+pub const TOURNAMENT_PAIR_RETRY: u64 = 10;
+pub const MAX_SPECTATOR_DELAY: u64 = 10 * 60;
+pub const MAX_TURN_TIME_LIMIT: u64 = 10 * 60;
+pub const DISCONNECT_GRACE: u64 = 30;
+pub const AUTO_PLAY_DELAY: u64 = 1;
+pub const REPLAY_BUFFER_LEN: usize = 256;
+pub const HEARTBEAT_INTERVAL: u64 = 15;
+pub const PONG_TIMEOUT: u64 = 45;
+pub const CLIENT_QUEUE_CAPACITY: usize = 512;
+pub const GAME_QUEUE_CAPACITY: usize = 1024;
+pub const SPECTATOR_RELAY_CAPACITY: usize = 4096;
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+pub const HANDSHAKE_TIMEOUT: u64 = 10;
+pub const TX_SHARDS: usize = 64;
@@ -3,9 +3,12 @@ use std::sync::Arc;
 use deadpool_sqlite::{Pool, rusqlite};
 use serde::Serialize;
 
+use crate::config::{BOT_NAME_PREFIX, DEFAULT_RATING, RATING_K};
 use crate::error::AppError;
-use crate::game::Game;
+use crate::game::{ChatRecord, Game};
 use crate::query_data::{GameDetail, RoundDetail};
+use crate::room::SavedGame;
+use crate::tournament::{Pairing, Standing, Tournament, TournamentStatus, TournamentTable};
 
 pub async fn init_db(db_pool: &Pool) -> Result<(), AppError> {
     let db_conn = db_pool.get().await?;
@@ -45,6 +48,66 @@ pub async fn init_db(db_pool: &Pool) -> Result<(), AppError> {
                 )",
                 (),
             )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS game_chats(
+                    game_id INTEGER NOT NULL,
+                    uid INTEGER NOT NULL,
+                    ts INTEGER NOT NULL,
+                    text TEXT NOT NULL
+                )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS running_games(
+                    room_id INTEGER PRIMARY KEY,
+                    state TEXT NOT NULL
+                )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS tournaments(
+                    tournament_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    owner INTEGER NOT NULL,
+                    hanchan INTEGER NOT NULL,
+                    pairing TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    current_round INTEGER NOT NULL
+                )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS tournament_players(
+                    tournament_id INTEGER NOT NULL,
+                    uid INTEGER NOT NULL,
+                    UNIQUE(tournament_id, uid)
+                )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS tournament_games(
+                    tournament_id INTEGER NOT NULL,
+                    round INTEGER NOT NULL,
+                    room_id INTEGER NOT NULL,
+                    players TEXT NOT NULL,
+                    game_id INTEGER,
+                    finished INTEGER NOT NULL
+                )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS bots(
+                    uid INTEGER PRIMARY KEY
+                )",
+                (),
+            )?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS ratings(
+                    uid INTEGER PRIMARY KEY,
+                    rating REAL NOT NULL
+                )",
+                (),
+            )?;
             return Ok(());
         })
         .await?;
@@ -74,6 +137,50 @@ pub async fn add_user(db_pool: &Pool, username: &str, passhash: &str) -> Result<
         .await?;
 }
 
+pub async fn query_bots(db_pool: &Pool) -> Result<Vec<u64>, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(|conn| {
+            let mut stmt = conn.prepare("SELECT uid FROM bots ORDER BY uid ASC")?;
+            let rows = stmt.query_map((), |row| row.get(0))?;
+            let mut res = Vec::new();
+            for row in rows {
+                res.push(row?);
+            }
+            return Ok(res);
+        })
+        .await?;
+}
+
+// bot accounts have an empty passhash, which no password hashes to
+pub async fn add_bot(db_pool: &Pool) -> Result<u64, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(|conn| {
+            let tx = conn.transaction()?;
+            let count: u64 = tx.query_row("SELECT COUNT(*) FROM bots", (), |row| row.get(0))?;
+            let mut n = count + 1;
+            let uid: u64 = loop {
+                let res = tx.query_row(
+                    "INSERT INTO users(username, passhash) VALUES (?1, '')
+                    ON CONFLICT(username) DO NOTHING
+                    RETURNING uid",
+                    (format!("{}{}", BOT_NAME_PREFIX, n),),
+                    |row| row.get(0),
+                );
+                match res {
+                    Ok(uid) => break uid,
+                    Err(rusqlite::Error::QueryReturnedNoRows) => n += 1,
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            tx.execute("INSERT INTO bots(uid) VALUES (?1)", (uid,))?;
+            tx.commit()?;
+            return Ok(uid);
+        })
+        .await?;
+}
+
 pub async fn query_username(db_pool: &Pool, uid: u64) -> Result<String, AppError> {
     let db_conn = db_pool.get().await?;
     let db_param = (uid,);
@@ -124,7 +231,8 @@ pub async fn verify_passhash(
         .await?;
 }
 
-pub async fn add_game(db_pool: &Pool, game: Arc<Game>) -> Result<usize, AppError> {
+// also drops the saved state of the game, it is finished now
+pub async fn add_game(db_pool: &Pool, room_id: usize, game: Arc<Game>) -> Result<usize, AppError> {
     let db_conn = db_pool.get().await?;
     return db_conn
         .interact(move |conn| {
@@ -155,6 +263,15 @@ pub async fn add_game(db_pool: &Pool, game: Arc<Game>) -> Result<usize, AppError
                 )?;
             }
 
+            for chat in game.chat_records.iter() {
+                tx.execute(
+                    "INSERT INTO game_chats(game_id, uid, ts, text) VALUES (?1, ?2, ?3, ?4)",
+                    (game_id, chat.uid, chat.ts, &chat.text),
+                )?;
+            }
+
+            tx.execute("DELETE FROM running_games WHERE room_id = ?1", (room_id,))?;
+
             tx.commit()?;
 
             return Ok(game_id);
@@ -162,6 +279,64 @@ pub async fn add_game(db_pool: &Pool, game: Arc<Game>) -> Result<usize, AppError
         .await?;
 }
 
+pub async fn save_running_game(
+    db_pool: &Pool,
+    room_id: usize,
+    saved: &SavedGame,
+) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    let state = serde_json::to_string(saved)?;
+    return db_conn
+        .interact(move |conn| {
+            conn.execute(
+                "INSERT INTO running_games(room_id, state) VALUES (?1, ?2)
+                ON CONFLICT(room_id) DO UPDATE SET state = excluded.state",
+                (room_id, state),
+            )?;
+            return Ok(());
+        })
+        .await?;
+}
+
+pub async fn delete_running_game(db_pool: &Pool, room_id: usize) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            conn.execute("DELETE FROM running_games WHERE room_id = ?1", (room_id,))?;
+            return Ok(());
+        })
+        .await?;
+}
+
+// states that no longer parse are dropped instead of blocking the startup
+pub async fn query_running_games(db_pool: &Pool) -> Result<Vec<(usize, SavedGame)>, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(|conn| {
+            let mut stmt = conn.prepare("SELECT room_id, state FROM running_games")?;
+            let rows = stmt.query_map((), |row| {
+                Ok((row.get::<_, usize>(0)?, row.get::<_, String>(1)?))
+            })?;
+            let mut res = Vec::new();
+            let mut broken = Vec::new();
+            for row in rows {
+                let (room_id, state) = row?;
+                match serde_json::from_str(&state) {
+                    Ok(saved) => res.push((room_id, saved)),
+                    Err(e) => {
+                        tracing::error!("{:?}", e);
+                        broken.push(room_id);
+                    }
+                }
+            }
+            for room_id in broken {
+                conn.execute("DELETE FROM running_games WHERE room_id = ?1", (room_id,))?;
+            }
+            return Ok(res);
+        })
+        .await?;
+}
+
 pub async fn query_rankings(db_pool: &Pool, game_id: usize) -> Result<Vec<u64>, AppError> {
     let db_conn = db_pool.get().await?;
     return db_conn
@@ -205,6 +380,37 @@ pub async fn query_game_detail(db_pool: &Pool, game_id: usize) -> Result<GameDet
         .await?;
 }
 
+pub async fn query_game_chat(db_pool: &Pool, game_id: usize) -> Result<Vec<ChatRecord>, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let exist: bool = conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM games WHERE game_id = ?1)",
+                (game_id,),
+                |row| row.get(0),
+            )?;
+            if !exist {
+                return Err(AppError::GameNotExist);
+            }
+            let mut stmt = conn.prepare(
+                "SELECT uid, ts, text FROM game_chats WHERE game_id = ?1 ORDER BY rowid ASC",
+            )?;
+            let rows = stmt.query_map((game_id,), |row| {
+                Ok(ChatRecord {
+                    uid: row.get(0)?,
+                    ts: row.get(1)?,
+                    text: row.get(2)?,
+                })
+            })?;
+            let mut res = Vec::new();
+            for row in rows {
+                res.push(row?);
+            }
+            return Ok(res);
+        })
+        .await?;
+}
+
 pub async fn query_round_detail(
     db_pool: &Pool,
     game_id: usize,
@@ -237,3 +443,362 @@ pub async fn query_round_detail(
         })
         .await?;
 }
+
+pub async fn query_rating(db_pool: &Pool, uid: u64) -> Result<f64, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let res = conn.query_row("SELECT rating FROM ratings WHERE uid = ?1", (uid,), |row| {
+                row.get(0)
+            });
+            match res {
+                Ok(res) => return Ok(res),
+                Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(DEFAULT_RATING),
+                Err(e) => return Err(e.into()),
+            }
+        })
+        .await?;
+}
+
+// pairwise elo between all four players, ranked by final score
+pub async fn update_ratings(
+    db_pool: &Pool,
+    players: [u64; 4],
+    players_score: [i64; 4],
+) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let tx = conn.transaction()?;
+
+            let mut ratings = [DEFAULT_RATING; 4];
+            for i in 0..4 {
+                let res = tx.query_row(
+                    "SELECT rating FROM ratings WHERE uid = ?1",
+                    (players[i],),
+                    |row| row.get(0),
+                );
+                match res {
+                    Ok(res) => ratings[i] = res,
+                    Err(rusqlite::Error::QueryReturnedNoRows) => (),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let mut delta = [0.0; 4];
+            for i in 0..4 {
+                for j in 0..4 {
+                    if i == j {
+                        continue;
+                    }
+                    let expected = 1.0 / (1.0 + 10f64.powf((ratings[j] - ratings[i]) / 400.0));
+                    let actual = match players_score[i].cmp(&players_score[j]) {
+                        std::cmp::Ordering::Greater => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Less => 0.0,
+                    };
+                    delta[i] += RATING_K * (actual - expected) / 3.0;
+                }
+            }
+
+            for i in 0..4 {
+                tx.execute(
+                    "INSERT INTO ratings(uid, rating) VALUES (?1, ?2)
+                    ON CONFLICT(uid) DO UPDATE SET rating = excluded.rating",
+                    (players[i], ratings[i] + delta[i]),
+                )?;
+            }
+
+            tx.commit()?;
+            return Ok(());
+        })
+        .await?;
+}
+
+pub async fn add_tournament(
+    db_pool: &Pool,
+    name: &str,
+    owner: u64,
+    hanchan: usize,
+    pairing: Pairing,
+) -> Result<u64, AppError> {
+    let db_conn = db_pool.get().await?;
+    let db_params = (
+        name.to_string(),
+        owner,
+        hanchan,
+        pairing.as_str(),
+        TournamentStatus::Registering.as_str(),
+    );
+    return db_conn
+        .interact(|conn| {
+            let tournament_id = conn.query_row(
+                "INSERT INTO tournaments(name, owner, hanchan, pairing, status, current_round)
+                VALUES (?1, ?2, ?3, ?4, ?5, 0) RETURNING tournament_id",
+                db_params,
+                |row| row.get(0),
+            )?;
+            return Ok(tournament_id);
+        })
+        .await?;
+}
+
+pub async fn query_tournament(db_pool: &Pool, tournament_id: u64) -> Result<Tournament, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let res = conn.query_row(
+                "SELECT name, owner, hanchan, pairing, status, current_round
+                FROM tournaments WHERE tournament_id = ?1",
+                (tournament_id,),
+                |row| {
+                    let pairing: String = row.get(3)?;
+                    let status: String = row.get(4)?;
+                    Ok(Tournament {
+                        tournament_id,
+                        name: row.get(0)?,
+                        owner: row.get(1)?,
+                        hanchan: row.get(2)?,
+                        pairing: Pairing::from_db(&pairing),
+                        status: TournamentStatus::from_db(&status),
+                        current_round: row.get(5)?,
+                    })
+                },
+            );
+            match res {
+                Ok(res) => return Ok(res),
+                Err(rusqlite::Error::QueryReturnedNoRows) => {
+                    return Err(AppError::TournamentNotExist);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        })
+        .await?;
+}
+
+/// Moves a registering tournament to running, false if it already left registration.
+pub async fn start_tournament(db_pool: &Pool, tournament_id: u64) -> Result<bool, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let rows = conn.execute(
+                "UPDATE tournaments SET status = ?2 WHERE tournament_id = ?1 AND status = ?3",
+                (
+                    tournament_id,
+                    TournamentStatus::Running.as_str(),
+                    TournamentStatus::Registering.as_str(),
+                ),
+            )?;
+            return Ok(rows == 1);
+        })
+        .await?;
+}
+
+pub async fn update_tournament_round(
+    db_pool: &Pool,
+    tournament_id: u64,
+    status: TournamentStatus,
+    round: usize,
+) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            conn.execute(
+                "UPDATE tournaments SET status = ?2, current_round = ?3 WHERE tournament_id = ?1",
+                (tournament_id, status.as_str(), round),
+            )?;
+            return Ok(());
+        })
+        .await?;
+}
+
+pub async fn add_tournament_player(
+    db_pool: &Pool,
+    tournament_id: u64,
+    uid: u64,
+) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            match conn.execute(
+                "INSERT INTO tournament_players(tournament_id, uid) VALUES (?1, ?2)",
+                (tournament_id, uid),
+            ) {
+                Err(rusqlite::Error::SqliteFailure(info, _)) if info.extended_code == 2067 => {
+                    return Err(AppError::TournamentAlreadyRegistered);
+                }
+                Err(e) => return Err(e.into()),
+                Ok(_) => return Ok(()),
+            }
+        })
+        .await?;
+}
+
+pub async fn query_tournament_players(
+    db_pool: &Pool,
+    tournament_id: u64,
+) -> Result<Vec<u64>, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT uid FROM tournament_players WHERE tournament_id = ?1 ORDER BY rowid ASC",
+            )?;
+            let rows = stmt.query_map((tournament_id,), |row| row.get(0))?;
+            let mut res = Vec::new();
+            for row in rows {
+                res.push(row?);
+            }
+            return Ok(res);
+        })
+        .await?;
+}
+
+pub async fn add_tournament_tables(
+    db_pool: &Pool,
+    tournament_id: u64,
+    round: usize,
+    tables: Vec<(usize, [u64; 4])>,
+) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let tx = conn.transaction()?;
+            for (room_id, players) in tables {
+                tx.execute(
+                    "INSERT INTO tournament_games(tournament_id, round, room_id, players, finished)
+                    VALUES (?1, ?2, ?3, ?4, 0)",
+                    (
+                        tournament_id,
+                        round,
+                        room_id,
+                        serde_json::to_string(&players)?,
+                    ),
+                )?;
+            }
+            tx.commit()?;
+            return Ok(());
+        })
+        .await?;
+}
+
+pub async fn delete_tournament_tables(
+    db_pool: &Pool,
+    tournament_id: u64,
+    round: usize,
+    room_ids: Vec<usize>,
+) -> Result<(), AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let tx = conn.transaction()?;
+            for room_id in room_ids {
+                tx.execute(
+                    "DELETE FROM tournament_games
+                    WHERE tournament_id = ?1 AND round = ?2 AND room_id = ?3",
+                    (tournament_id, round, room_id),
+                )?;
+            }
+            tx.commit()?;
+            return Ok(());
+        })
+        .await?;
+}
+
+// returns whether every table of the round has finished
+pub async fn finish_tournament_table(
+    db_pool: &Pool,
+    tournament_id: u64,
+    round: usize,
+    room_id: usize,
+    game_id: Option<usize>,
+) -> Result<bool, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE tournament_games SET game_id = ?4, finished = 1
+                WHERE tournament_id = ?1 AND round = ?2 AND room_id = ?3",
+                (tournament_id, round, room_id, game_id),
+            )?;
+            let remaining: usize = tx.query_row(
+                "SELECT COUNT(*) FROM tournament_games
+                WHERE tournament_id = ?1 AND round = ?2 AND finished = 0",
+                (tournament_id, round),
+                |row| row.get(0),
+            )?;
+            tx.commit()?;
+            return Ok(remaining == 0);
+        })
+        .await?;
+}
+
+pub async fn query_tournament_tables(
+    db_pool: &Pool,
+    tournament_id: u64,
+) -> Result<Vec<TournamentTable>, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT round, room_id, players, game_id, finished FROM tournament_games
+                WHERE tournament_id = ?1 ORDER BY round ASC, room_id ASC",
+            )?;
+            let rows = stmt.query_map((tournament_id,), |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?;
+            let mut res = Vec::new();
+            for row in rows {
+                let (round, room_id, players, game_id, finished) = row?;
+                res.push(TournamentTable {
+                    round,
+                    room_id,
+                    players: serde_json::from_str(&players)?,
+                    game_id,
+                    finished,
+                });
+            }
+            return Ok(res);
+        })
+        .await?;
+}
+
+// cumulative scores over finished tournament games, best first
+pub async fn query_standings(
+    db_pool: &Pool,
+    tournament_id: u64,
+) -> Result<Vec<Standing>, AppError> {
+    let db_conn = db_pool.get().await?;
+    return db_conn
+        .interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT tp.uid, COALESCE(SUM(gp.score), 0), COUNT(gp.game_id)
+                FROM tournament_players tp
+                LEFT JOIN tournament_games tg ON tg.tournament_id = tp.tournament_id
+                LEFT JOIN game_players gp ON gp.game_id = tg.game_id AND gp.uid = tp.uid
+                WHERE tp.tournament_id = ?1
+                GROUP BY tp.uid
+                ORDER BY 2 DESC, tp.uid ASC",
+            )?;
+            let rows = stmt.query_map((tournament_id,), |row| {
+                Ok(Standing {
+                    uid: row.get(0)?,
+                    score: row.get(1)?,
+                    games: row.get(2)?,
+                })
+            })?;
+            let mut res = Vec::new();
+            for row in rows {
+                res.push(row?);
+            }
+            return Ok(res);
+        })
+        .await?;
+}
@@ -41,12 +41,96 @@ pub enum AppError {
     #[error("")]
     RoomNotFull,
 
+    #[error("")]
+    RoomNotReady,
+
+    #[error("")]
+    GameAlreadyStart,
+
+    #[error("")]
+    SeatNotExist,
+
+    #[error("")]
+    SeatTaken,
+
+    #[error("")]
+    SeatNotChosen,
+
+    #[error("")]
+    RoomPasswordIncorrect,
+
+    #[error("")]
+    InviteCodeIncorrect,
+
+    #[error("")]
+    InvalidRules,
+
+    #[error("")]
+    UserAlreadyInQueue,
+
+    #[error("")]
+    UserNotInQueue,
+
+    #[error("")]
+    NotRoomOwner,
+
+    #[error("")]
+    CannotKickSelf,
+
+    #[error("")]
+    RematchNotOpen,
+
+    #[error("")]
+    TournamentNotExist,
+
+    #[error("")]
+    TournamentAlreadyStart,
+
+    #[error("")]
+    TournamentAlreadyRegistered,
+
+    #[error("")]
+    NotTournamentOwner,
+
+    #[error("")]
+    TournamentPlayerCount,
+
+    #[error("")]
+    TournamentPlayerBusy,
+
+    #[error("")]
+    ChatTooLong,
+
+    #[error("")]
+    ChatRateLimited,
+
+    #[error("")]
+    ChatNotAllowed,
+
     #[error("")]
     TxNotExist,
 
+    #[error("")]
+    TxAlreadyExist,
+
+    #[error("")]
+    TxFull,
+
+    #[error("")]
+    HandshakeFailed,
+
+    #[error("")]
+    IncompatibleVersion(u32),
+
+    #[error("")]
+    WebSocket(String),
+
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
 
+    #[error("")]
+    Encoding(String),
+
     #[error("mpsc send error: {0}")]
     MpscSend(String),
 
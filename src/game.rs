@@ -1,13 +1,40 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::time::Duration;
 
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::watch;
+use tokio::time::Instant;
 
-use crate::txmanager::TxManager;
-use crate::ws::{ClientMessage, GameInfo, ServerMessage};
+use crate::config::{AUTO_PLAY_DELAY, DISCONNECT_GRACE, SPECTATOR_RELAY_CAPACITY};
+use crate::error::AppError;
+use crate::room::RoomRules;
+use crate::session::Sessions;
+use crate::txmanager::{FullPolicy, Rx, Tx, channel};
+use crate::ws::{
+    ClientMessage, ClientRequest, ErrorCode, GameInfo, Prompt, ServerMessage, TableSnapshot,
+};
+
+#[derive(Debug)]
+pub enum GameMessage {
+    Client((u64, ClientRequest)),
+    Spectate(u64),
+    Unspectate(u64),
+    Chat(ChatRecord),
+    // a player or spectator opened or closed a WebSocket
+    Connected(u64),
+    Disconnected(u64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatRecord {
+    pub uid: u64,
+    pub text: String,
+    pub ts: u64,
+}
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Cards {
@@ -81,7 +108,9 @@ impl Cards {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 struct Stack {
+    #[serde(with = "serde_bytes")]
     stack: [u8; 136],
     next: usize,
 }
@@ -106,10 +135,15 @@ impl Stack {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Round {
     stack: Stack,
     current_player: usize,
     players_cards: [Cards; 4],
+    // the card drawn by the current player
+    last_draw: u8,
+    // discards of each seat in order
+    rivers: [Vec<u8>; 4],
 }
 
 impl Round {
@@ -121,83 +155,500 @@ impl Round {
                 players_cards[i].insert(stack.next());
             }
         }
-        players_cards[host].insert(stack.next());
+        let last_draw = stack.next();
+        players_cards[host].insert(last_draw);
         return Round {
             stack,
             current_player: host,
             players_cards,
+            last_draw,
+            rivers: Default::default(),
         };
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RoundRecord {
+    #[serde(with = "serde_bytes")]
     pub stack: [u8; 136],
     pub winner_seat: Option<usize>,
     pub loser_seat: Option<usize>,
     pub discard: Vec<u8>,
 }
 
+// what survives a restart, the rest of `Game` starts over when it is restored
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameState {
+    pub round: Round,
+    pub round_id: usize,
+    pub rules: RoomRules,
+    pub players: [u64; 4],
+    pub bots: HashSet<u64>,
+    pub players_score: [i64; 4],
+    pub paused: bool,
+    pub round_records: Vec<RoundRecord>,
+    pub chat_records: Vec<ChatRecord>,
+}
+
 pub struct Game {
     pub round: Round,
     pub round_id: usize,
+    pub rules: RoomRules,
     pub players: [u64; 4],
+    // bots agree to every vote
+    pub bots: HashSet<u64>,
     pub players_score: [i64; 4],
-    pub conn: Arc<RwLock<TxManager<u64, ServerMessage>>>,
+    pub spectators: HashSet<u64>,
+    pub conn: Arc<Sessions>,
+
+    // delays messages to spectators, only present if the room asks for a delay
+    spectator_relay: Option<Tx<(Instant, u64, ServerMessage)>>,
+    // the current player discards their draw automatically after this
+    turn_deadline: Option<Instant>,
+
+    pub paused: bool,
+    // players voting to flip `paused`
+    pause_votes: HashSet<u64>,
+    // time left on the turn timer when the game was paused
+    paused_remaining: Option<Duration>,
+    turn_started: Instant,
+    // players without a WebSocket and when they lost it
+    pub disconnected: HashMap<u64, Instant>,
 
     pub round_records: Vec<RoundRecord>,
+    pub chat_records: Vec<ChatRecord>,
+
+    // receives the state after every event, only present if someone saves it
+    snapshots: Option<watch::Sender<GameState>>,
+}
+
+// a relay too far behind drops messages, spectators resync by reconnecting
+fn spawn_spectator_relay(conn: Arc<Sessions>) -> Tx<(Instant, u64, ServerMessage)> {
+    let (tx, mut rx) = channel(SPECTATOR_RELAY_CAPACITY, FullPolicy::Reject);
+    tokio::spawn(async move {
+        while let Some((deliver_at, uid, msg)) = rx.recv().await {
+            tokio::time::sleep_until(deliver_at).await;
+            match conn.send(&uid, msg) {
+                Err(e) => tracing::error!("{:?}", e),
+                Ok(_) => (),
+            }
+        }
+    });
+    return tx;
 }
 
 impl Game {
-    pub fn new(players: [u64; 4], conn: Arc<RwLock<TxManager<u64, ServerMessage>>>) -> Game {
+    pub fn new(
+        players: [u64; 4],
+        bots: HashSet<u64>,
+        rules: RoomRules,
+        conn: Arc<Sessions>,
+    ) -> Game {
+        let spectator_relay = if rules.spectator_delay > 0 {
+            Some(spawn_spectator_relay(conn.clone()))
+        } else {
+            None
+        };
         let game = Game {
             round: Round::new(0),
             round_id: 0,
+            rules,
             players,
+            bots,
             players_score: [0; 4],
+            spectators: HashSet::new(),
             conn,
+            spectator_relay,
+            turn_deadline: None,
+            paused: false,
+            pause_votes: HashSet::new(),
+            paused_remaining: None,
+            turn_started: Instant::now(),
+            disconnected: HashMap::new(),
             round_records: Vec::with_capacity(4),
+            chat_records: Vec::new(),
+            snapshots: None,
         };
         return game;
     }
 
-    async fn send(&self, player: usize, msg: ServerMessage) {
-        match self.conn.read().await.send(&self.players[player], msg) {
+    /// Rebuilds a saved game, `run` continues it where it was saved.
+    pub fn restore(state: GameState, conn: Arc<Sessions>) -> Game {
+        let mut game = Game::new(state.players, state.bots, state.rules, conn);
+        game.round = state.round;
+        game.round_id = state.round_id;
+        game.players_score = state.players_score;
+        game.paused = state.paused;
+        game.round_records = state.round_records;
+        game.chat_records = state.chat_records;
+        return game;
+    }
+
+    pub fn save(&self) -> GameState {
+        return GameState {
+            round: self.round.clone(),
+            round_id: self.round_id,
+            rules: self.rules,
+            players: self.players,
+            bots: self.bots.clone(),
+            players_score: self.players_score,
+            paused: self.paused,
+            round_records: self.round_records.clone(),
+            chat_records: self.chat_records.clone(),
+        };
+    }
+
+    /// Returns a receiver that sees the state of the game after every event
+    /// until `run` returns.
+    pub fn watch(&mut self) -> watch::Receiver<GameState> {
+        let (tx, rx) = watch::channel(self.save());
+        self.snapshots = Some(tx);
+        return rx;
+    }
+
+    async fn send_uid(&self, uid: u64, msg: ServerMessage) {
+        let away = self.disconnected.contains_key(&uid);
+        match self.conn.send(&uid, msg) {
+            // the queue of a stopped bot is closed
+            Err(AppError::TxNotExist | AppError::MpscSend(_)) if away => (),
             Err(e) => tracing::error!("{:?}", e),
             Ok(_) => (),
         }
     }
 
-    pub async fn broadcast(&self, msg: ServerMessage) {
+    async fn send(&self, player: usize, msg: ServerMessage) {
+        self.send_uid(self.players[player], msg).await;
+    }
+
+    async fn ack(&mut self, player: usize, id: Option<u64>) {
+        if let Some(id) = id {
+            self.send(player, ServerMessage::Ack(id)).await;
+        }
+    }
+
+    async fn reject(&mut self, player: usize, id: Option<u64>, code: ErrorCode) {
+        self.send(player, ServerMessage::error(id, code)).await;
+    }
+
+    async fn send_spectator(&self, uid: u64, msg: ServerMessage) {
+        match &self.spectator_relay {
+            Some(relay) => {
+                let delay = Duration::from_secs(self.rules.spectator_delay);
+                // the delay is bounded by `room_rules`, never panic on a bad one anyway
+                let deliver_at = Instant::now()
+                    .checked_add(delay)
+                    .unwrap_or_else(Instant::now);
+                if let Err(e) = relay.send((deliver_at, uid, msg)) {
+                    tracing::error!("{:?}", e);
+                }
+            }
+            None => self.send_uid(uid, msg).await,
+        }
+    }
+
+    async fn send_spectators(&self, msg: ServerMessage) {
+        for &uid in self.spectators.iter() {
+            self.send_spectator(uid, msg.clone()).await;
+        }
+    }
+
+    /// Sends a public event to all players and spectators.
+    pub async fn broadcast(&mut self, msg: ServerMessage) {
         for j in 0..4 {
             self.send(j, msg.clone()).await;
         }
+        self.send_spectators(msg).await;
     }
 
-    pub async fn game_start(&mut self) {
-        let game_info = GameInfo {
+    /// Sends an event after the game is over, it is not sequenced since
+    /// nothing is left to resume.
+    pub async fn announce(&self, msg: ServerMessage) {
+        for uid in self.players {
+            self.send_uid(uid, msg.clone()).await;
+        }
+        self.send_spectators(msg).await;
+    }
+
+    pub async fn run(&mut self, mut rx: Rx<GameMessage>) {
+        // a restored game has already dealt its first round
+        if self.round_records.len() == 0 {
+            self.game_start().await;
+        } else {
+            self.game_resume().await;
+        }
+        loop {
+            if let Some(snapshots) = &self.snapshots {
+                snapshots.send_replace(self.save());
+            }
+            let deadline = self.next_deadline();
+            let timeout = async move {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                _ = timeout => {
+                    if self.turn_timeout().await {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let Some(msg) = msg else {
+                break;
+            };
+            let end = match msg {
+                GameMessage::Client((uid, req)) => self.handle_message(req, uid).await,
+                GameMessage::Spectate(uid) => {
+                    self.spectate(uid).await;
+                    false
+                }
+                GameMessage::Unspectate(uid) => {
+                    self.spectators.remove(&uid);
+                    false
+                }
+                GameMessage::Chat(record) => {
+                    self.chat_records.push(record);
+                    false
+                }
+                GameMessage::Connected(uid) => {
+                    self.reconnect(uid).await;
+                    false
+                }
+                GameMessage::Disconnected(uid) => {
+                    self.disconnect(uid).await;
+                    false
+                }
+            };
+            if end {
+                break;
+            }
+        }
+        self.snapshots = None;
+    }
+
+    fn start_turn(&mut self) {
+        self.turn_started = Instant::now();
+        if self.rules.turn_time_limit > 0 {
+            let limit = Duration::from_secs(self.rules.turn_time_limit);
+            // the limit is bounded by `room_rules`, an overflow means no limit
+            self.turn_deadline = Instant::now().checked_add(limit);
+        }
+    }
+
+    // the earlier of the turn limit and auto-play for a disconnected current player
+    fn next_deadline(&self) -> Option<Instant> {
+        if self.paused {
+            // nobody is left to vote for a player who stays away
+            return self
+                .disconnected
+                .values()
+                .map(|&at| at + Duration::from_secs(DISCONNECT_GRACE))
+                .min();
+        }
+        let current = self.players[self.round.current_player];
+        let auto_play = self.disconnected.get(&current).map(|&at| {
+            let grace = at + Duration::from_secs(DISCONNECT_GRACE);
+            grace.max(self.turn_started + Duration::from_secs(AUTO_PLAY_DELAY))
+        });
+        match (self.turn_deadline, auto_play) {
+            (Some(a), Some(b)) => return Some(a.min(b)),
+            (a, b) => return a.or(b),
+        }
+    }
+
+    // discards the drawn card for an idle or absent player
+    async fn turn_timeout(&mut self) -> bool {
+        if self.paused {
+            tracing::info!("resume after a player stayed disconnected");
+            self.unpause().await;
+            return false;
+        }
+        let player = self.round.current_player;
+        tracing::info!("auto discard for player {}", self.players[player]);
+        let card = self.round.last_draw;
+        return self
+            .handle_message(ClientMessage::Discard(card).into(), self.players[player])
+            .await;
+    }
+
+    async fn vote_pause(&mut self, player: usize, pause: bool) -> Result<(), ErrorCode> {
+        if self.paused == pause {
+            if pause {
+                return Err(ErrorCode::GamePaused);
+            } else {
+                return Err(ErrorCode::GameNotPaused);
+            }
+        }
+
+        let uid = self.players[player];
+        self.pause_votes.insert(uid);
+        self.broadcast(ServerMessage::PauseVote((uid, pause))).await;
+        if pause {
+            let agreed = self
+                .players
+                .iter()
+                .all(|uid| self.bots.contains(uid) || self.pause_votes.contains(uid));
+            if agreed {
+                self.pause_votes.clear();
+                self.paused = true;
+                self.paused_remaining = self
+                    .turn_deadline
+                    .take()
+                    .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                self.broadcast(ServerMessage::Paused).await;
+            }
+        } else if self.resume_agreed() {
+            self.unpause().await;
+        }
+        return Ok(());
+    }
+
+    // disconnected players do not hold a paused game back
+    fn resume_agreed(&self) -> bool {
+        return self.players.iter().all(|uid| {
+            self.bots.contains(uid)
+                || self.pause_votes.contains(uid)
+                || self.disconnected.contains_key(uid)
+        });
+    }
+
+    async fn unpause(&mut self) {
+        self.pause_votes.clear();
+        self.paused = false;
+        self.turn_deadline = self
+            .paused_remaining
+            .take()
+            .and_then(|remaining| Instant::now().checked_add(remaining));
+        self.broadcast(ServerMessage::Resumed).await;
+    }
+
+    fn game_info(&self) -> GameInfo {
+        return GameInfo {
             round_id: self.round_id,
             players: self.players,
             players_score: self.players_score,
         };
-        self.broadcast(ServerMessage::GameInfoSync(game_info)).await;
+    }
+
+    fn snapshot(&self, uid: u64) -> TableSnapshot {
+        let seat = self.players.iter().position(|&i| i == uid);
+        let mut prompts = Vec::new();
+        if let Some(seat) = seat {
+            if self.paused && !self.pause_votes.contains(&uid) {
+                prompts.push(Prompt::PauseVote(false));
+            } else if !self.pause_votes.is_empty() && !self.pause_votes.contains(&uid) {
+                prompts.push(Prompt::PauseVote(true));
+            }
+            if !self.paused && seat == self.round.current_player {
+                prompts.push(Prompt::Discard);
+            }
+        }
+        return TableSnapshot {
+            game_info: self.game_info(),
+            current_player: self.players[self.round.current_player],
+            rivers: self.round.rivers.clone(),
+            wall_remaining: 136 - self.round.stack.next,
+            paused: self.paused,
+            disconnected: self.disconnected.keys().copied().collect(),
+            prompts,
+            hand: seat.map(|seat| self.round.players_cards[seat]),
+        };
+    }
+
+    async fn send_snapshot(&mut self, uid: u64) {
+        let msg = ServerMessage::TableSnapshot(self.snapshot(uid));
+        if let Some(seat) = self.players.iter().position(|&i| i == uid) {
+            self.send(seat, msg).await;
+        } else if self.spectators.contains(&uid) {
+            self.send_spectator(uid, msg).await;
+        }
+    }
+
+    async fn spectate(&mut self, uid: u64) {
+        self.spectators.insert(uid);
+        self.send_spectator(uid, ServerMessage::GameInfoSync(self.game_info()))
+            .await;
+        self.send_spectator(uid, ServerMessage::RoundStart(self.round_id))
+            .await;
+    }
+
+    async fn disconnect(&mut self, uid: u64) {
+        if !self.players.contains(&uid) {
+            return;
+        }
+        self.disconnected.insert(uid, Instant::now());
+        self.broadcast(ServerMessage::PlayerDisconnected(uid)).await;
+        // the others may all have voted to resume already
+        if self.paused && self.pause_votes.len() != 0 && self.resume_agreed() {
+            self.unpause().await;
+        }
+    }
+
+    async fn reconnect(&mut self, uid: u64) {
+        if self.disconnected.remove(&uid).is_some() {
+            self.broadcast(ServerMessage::PlayerReconnected(uid)).await;
+        }
+        self.send_snapshot(uid).await;
+    }
+
+    pub async fn game_start(&mut self) {
+        // players without a connection count as disconnected from the start
+        let now = Instant::now();
+        for uid in self.players {
+            if !self.bots.contains(&uid) && !self.conn.contains(&uid) {
+                self.disconnected.insert(uid, now);
+            }
+        }
+        self.broadcast(ServerMessage::GameInfoSync(self.game_info()))
+            .await;
         self.round_start().await;
     }
 
+    // nobody is connected to a restored game yet, bots are dealt their hands again
+    async fn game_resume(&mut self) {
+        let now = Instant::now();
+        for i in 0..4 {
+            let uid = self.players[i];
+            if !self.bots.contains(&uid) && !self.conn.contains(&uid) {
+                self.disconnected.insert(uid, now);
+            } else if self.bots.contains(&uid) {
+                self.send(i, ServerMessage::CardSync(self.round.players_cards[i]))
+                    .await;
+            }
+        }
+        if !self.paused {
+            self.start_turn();
+        } else if self.rules.turn_time_limit > 0 {
+            self.paused_remaining = Some(Duration::from_secs(self.rules.turn_time_limit));
+        }
+    }
+
     pub async fn round_start(&mut self) {
         for i in 0..4 {
             self.send(i, ServerMessage::RoundStart(self.round_id)).await;
             self.send(i, ServerMessage::CardSync(self.round.players_cards[i]))
                 .await;
         }
+        self.send_spectators(ServerMessage::RoundStart(self.round_id))
+            .await;
         self.round_records.push(RoundRecord {
             stack: self.round.stack.stack,
             winner_seat: None,
             loser_seat: None,
             discard: Vec::new(),
         });
+        self.start_turn();
     }
 
     async fn next_round(&mut self) -> bool {
+        if self.rules.spectator_open_hands {
+            self.send_spectators(ServerMessage::HandsReveal(self.round.players_cards))
+                .await;
+        }
+
         self.round_id += 1;
 
         // check game end
@@ -263,50 +714,98 @@ impl Game {
         return self.next_round().await;
     }
 
-    pub async fn handle_message(&mut self, msg: ClientMessage, uid: u64) -> bool {
-        tracing::debug!("handle msg {:?} from {}", msg, uid);
+    pub async fn handle_message(&mut self, req: ClientRequest, uid: u64) -> bool {
+        tracing::debug!("handle msg {:?} from {}", req, uid);
+        let ClientRequest { id, msg } = req;
         let mut player = None;
         for i in 0..4 {
             if self.players[i] == uid {
                 player = Some(i);
             }
         }
-        let player = player.unwrap();
+        let player = match player {
+            Some(player) => player,
+            None => {
+                if !self.spectators.contains(&uid) {
+                    let msg = ServerMessage::error(id, ErrorCode::UserNotInRoom);
+                    self.send_uid(uid, msg).await;
+                } else if let ClientMessage::RequestGameSync = msg {
+                    self.send_spectator(uid, ServerMessage::GameInfoSync(self.game_info()))
+                        .await;
+                    if let Some(id) = id {
+                        self.send_spectator(uid, ServerMessage::Ack(id)).await;
+                    }
+                } else {
+                    let msg = ServerMessage::error(id, ErrorCode::NotPlayer);
+                    self.send_uid(uid, msg).await;
+                }
+                return false;
+            }
+        };
 
         match msg {
+            // handled by the room or the connection
+            ClientMessage::Hello { .. }
+            | ClientMessage::Chat(_)
+            | ClientMessage::RematchVote(_)
+            | ClientMessage::SubscribeLobby
+            | ClientMessage::UnsubscribeLobby => return false,
             ClientMessage::RequestGameSync => {
-                let game_info = GameInfo {
-                    round_id: self.round_id,
-                    players: self.players,
-                    players_score: self.players_score,
-                };
-                self.send(player, ServerMessage::GameInfoSync(game_info))
+                self.send(player, ServerMessage::GameInfoSync(self.game_info()))
                     .await;
+                self.ack(player, id).await;
+                return false;
+            }
+            // the connection could not replay what was missed
+            ClientMessage::Resume { .. } => {
+                self.send_snapshot(self.players[player]).await;
+                self.ack(player, id).await;
                 return false;
             }
             ClientMessage::RequestCardSync => {
                 let cards = self.round.players_cards[player];
                 self.send(player, ServerMessage::CardSync(cards)).await;
+                self.ack(player, id).await;
+                return false;
+            }
+            ClientMessage::RequestPause => {
+                match self.vote_pause(player, true).await {
+                    Ok(_) => self.ack(player, id).await,
+                    Err(code) => self.reject(player, id, code).await,
+                }
+                return false;
+            }
+            ClientMessage::RequestResume => {
+                match self.vote_pause(player, false).await {
+                    Ok(_) => self.ack(player, id).await,
+                    Err(code) => self.reject(player, id, code).await,
+                }
                 return false;
             }
             ClientMessage::Discard(card) => {
+                if self.paused {
+                    self.reject(player, id, ErrorCode::GamePaused).await;
+                    return false;
+                }
                 if player != self.round.current_player {
-                    self.send(player, ServerMessage::NotCurrentPlayer).await;
+                    self.reject(player, id, ErrorCode::NotCurrentPlayer).await;
                     return false;
                 }
 
                 // check if the card can be discard
                 if self.round.players_cards[player][card as usize] == 0 {
-                    self.send(player, ServerMessage::NotHaveCard).await;
+                    self.reject(player, id, ErrorCode::NotHaveCard).await;
                     return false;
                 }
 
                 // broadcast discard
                 self.broadcast(ServerMessage::Discard((self.players[player], card)))
                     .await;
+                self.ack(player, id).await;
 
                 // discard
                 self.round.players_cards[player].delete(card);
+                self.round.rivers[player].push(card);
 
                 // record discard
                 if let Some(record) = self.round_records.last_mut() {
@@ -332,6 +831,7 @@ impl Game {
                 let next_card = self.round.stack.next();
                 let next_player = (player + 1) % 4;
                 self.round.players_cards[next_player].insert(next_card);
+                self.round.last_draw = next_card;
                 self.send(next_player, ServerMessage::GetCard(next_card))
                     .await;
 
@@ -342,6 +842,7 @@ impl Game {
 
                 // maintain current_player
                 self.round.current_player = next_player;
+                self.start_turn();
                 return false;
             }
         }
@@ -349,78 +850,262 @@ impl Game {
 }
 
 pub mod check_win {
-    use crate::game::Cards;
+    #[cfg(not(feature = "win-table"))]
+    pub use dp::check;
+    #[cfg(feature = "win-table")]
+    pub use table::check;
 
-    type State = [[i32; 3]; 3];
+    pub mod dp {
+        use crate::game::Cards;
 
-    fn transition(u: &State, x: i32) -> State {
-        let mut v = [[-1; 3]; 3];
-        for i in 0..3 {
-            for j in (0..3).take_while(|&j| u[i][j] != -1) {
-                for k in (0..3).take_while(|&k| i + j + k <= x as usize) {
-                    let nv = u[i][j] + i as i32 + (x as usize >= 3 + i + j + k) as i32;
-                    v[j][k] = v[j][k].max(nv);
+        pub(super) type State = [[i32; 3]; 3];
+
+        pub(super) fn transition(u: &State, x: i32) -> State {
+            let mut v = [[-1; 3]; 3];
+            for i in 0..3 {
+                for j in (0..3).take_while(|&j| u[i][j] != -1) {
+                    for k in (0..3).take_while(|&k| i + j + k <= x as usize) {
+                        let nv = u[i][j] + i as i32 + (x as usize >= 3 + i + j + k) as i32;
+                        v[j][k] = v[j][k].max(nv);
+                    }
                 }
             }
+            return v;
         }
-        return v;
-    }
 
-    fn next_same_suit(f0: &mut State, f1: &mut State, count: &mut i32, x: i32) {
-        *f0 = transition(f0, x);
-        *f1 = transition(f1, x);
-        if x >= 2 {
-            *count += 1;
-            let nf1 = transition(f0, x - 2);
+        pub(super) fn next_same_suit(f0: &mut State, f1: &mut State, count: &mut i32, x: i32) {
+            *f0 = transition(f0, x);
+            *f1 = transition(f1, x);
+            if x >= 2 {
+                *count += 1;
+                let nf1 = transition(f0, x - 2);
+                for i in 0..3 {
+                    for j in 0..3 {
+                        f1[i][j] = f1[i][j].max(nf1[i][j]);
+                    }
+                }
+            }
+        }
+
+        pub(super) fn switch_suit(f0: &mut State, f1: &mut State) {
+            for i in 0..3 {
+                for j in 0..3 {
+                    if i == 0 && j == 0 {
+                        continue;
+                    }
+                    f0[i][j] = -1;
+                    f1[i][j] = -1;
+                }
+            }
+        }
+
+        pub fn check(cards: &Cards) -> bool {
+            let mut f0 = [[-1; 3]; 3];
+            let mut f1 = [[-1; 3]; 3];
+            let mut count = 0;
+
+            f0[0][0] = 0;
+            for idx in 0..9 {
+                next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+            }
+            switch_suit(&mut f0, &mut f1);
+            for idx in 9..18 {
+                next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+            }
+            switch_suit(&mut f0, &mut f1);
+            for idx in 18..27 {
+                next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+            }
+            switch_suit(&mut f0, &mut f1);
+            for idx in 27..34 {
+                next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+                switch_suit(&mut f0, &mut f1);
+            }
+
+            let mut mx = 0;
             for i in 0..3 {
                 for j in 0..3 {
-                    f1[i][j] = f1[i][j].max(nf1[i][j]);
+                    mx = mx.max(f1[i][j]);
                 }
             }
+            return count >= 7 || mx >= 4;
         }
     }
 
-    fn switch_suit(f0: &mut State, f1: &mut State) {
-        for i in 0..3 {
-            for j in 0..3 {
-                if i == 0 && j == 0 {
-                    continue;
+    pub mod table {
+        use std::sync::LazyLock;
+
+        use super::dp::{self, State, next_same_suit, switch_suit};
+        use crate::game::Cards;
+
+        // suits with more tiles than this are not precomputed and fall back to dp
+        const MAX_SUIT_TILES: i32 = 14;
+        const NONE: u16 = u16::MAX;
+
+        // packed entry: melds without pair | (melds with pair + 1) << 4 | pairs << 8
+        static SUIT_TABLE: LazyLock<Vec<u16>> = LazyLock::new(build_suit_table);
+        static HONOR_TABLE: LazyLock<[u16; 5]> = LazyLock::new(build_honor_table);
+
+        fn pack(f0: &State, f1: &State, count: i32) -> u16 {
+            return f0[0][0] as u16 | ((f1[0][0] + 1) as u16) << 4 | (count as u16) << 8;
+        }
+
+        fn init_state() -> (State, State) {
+            let mut f0 = [[-1; 3]; 3];
+            let f1 = [[-1; 3]; 3];
+            f0[0][0] = 0;
+            return (f0, f1);
+        }
+
+        fn fill(
+            table: &mut [u16],
+            (f0, f1, count): (State, State, i32),
+            depth: usize,
+            key: usize,
+            tiles: i32,
+        ) {
+            if depth == 9 {
+                let (mut f0, mut f1) = (f0, f1);
+                switch_suit(&mut f0, &mut f1);
+                table[key] = pack(&f0, &f1, count);
+                return;
+            }
+            for x in (0..=4).take_while(|&x| tiles + x <= MAX_SUIT_TILES) {
+                let (mut nf0, mut nf1, mut ncount) = (f0, f1, count);
+                next_same_suit(&mut nf0, &mut nf1, &mut ncount, x);
+                let nkey = key + x as usize * 5usize.pow(depth as u32);
+                fill(table, (nf0, nf1, ncount), depth + 1, nkey, tiles + x);
+            }
+        }
+
+        fn build_suit_table() -> Vec<u16> {
+            let mut table = vec![NONE; 5usize.pow(9)];
+            let (f0, f1) = init_state();
+            fill(&mut table, (f0, f1, 0), 0, 0, 0);
+            return table;
+        }
+
+        fn build_honor_table() -> [u16; 5] {
+            let mut table = [NONE; 5];
+            for x in 0..5 {
+                let (mut f0, mut f1) = init_state();
+                let mut count = 0;
+                next_same_suit(&mut f0, &mut f1, &mut count, x);
+                switch_suit(&mut f0, &mut f1);
+                table[x as usize] = pack(&f0, &f1, count);
+            }
+            return table;
+        }
+
+        fn suit_entry(suit: &[u8]) -> u16 {
+            let mut key = 0;
+            for &x in suit.iter().rev() {
+                if x > 4 {
+                    return NONE;
+                }
+                key = key * 5 + x as usize;
+            }
+            return SUIT_TABLE[key];
+        }
+
+        fn honor_entry(x: u8) -> u16 {
+            return HONOR_TABLE.get(x as usize).copied().unwrap_or(NONE);
+        }
+
+        /// Same result as `dp::check`, answered from tables precomputed per suit.
+        pub fn check(cards: &Cards) -> bool {
+            let entries = (0..3)
+                .map(|i| suit_entry(&cards[i * 9..i * 9 + 9]))
+                .chain((27..34).map(|i| honor_entry(cards[i])));
+
+            let mut melds = 0;
+            let mut melds_with_pair = -1;
+            let mut count = 0;
+            for entry in entries {
+                if entry == NONE {
+                    return dp::check(cards);
+                }
+                let without_pair = (entry & 0xf) as i32;
+                let with_pair = ((entry >> 4) & 0xf) as i32 - 1;
+                count += (entry >> 8) as i32;
+
+                if melds_with_pair != -1 {
+                    melds_with_pair += without_pair;
+                }
+                if with_pair != -1 {
+                    melds_with_pair = melds_with_pair.max(melds + with_pair);
                 }
-                f0[i][j] = -1;
-                f1[i][j] = -1;
+                melds += without_pair;
             }
+            return count >= 7 || melds_with_pair >= 4;
         }
     }
 
-    pub fn check(cards: &Cards) -> bool {
-        let mut f0 = [[-1; 3]; 3];
-        let mut f1 = [[-1; 3]; 3];
-        let mut count = 0;
+    #[cfg(test)]
+    mod tests {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+        use rand::seq::SliceRandom;
 
-        f0[0][0] = 0;
-        for idx in 0..9 {
-            next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+        use super::{dp, table};
+        use crate::game::Cards;
+
+        fn assert_same(cards: &Cards) {
+            assert_eq!(table::check(cards), dp::check(cards), "{:?}", cards);
         }
-        switch_suit(&mut f0, &mut f1);
-        for idx in 9..18 {
-            next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+
+        // draws 14 tiles at a time from four copies of `kinds`
+        fn random_hands(kinds: impl Iterator<Item = u8>, n: usize) {
+            let mut rng = StdRng::seed_from_u64(20251010);
+            let mut stack: Vec<u8> = kinds.flat_map(|card| [card; 4]).collect();
+            for _ in 0..n {
+                stack.shuffle(&mut rng);
+                let mut cards = Cards::default();
+                for &card in stack.iter().take(14) {
+                    cards.insert(card);
+                }
+                assert_same(&cards);
+            }
         }
-        switch_suit(&mut f0, &mut f1);
-        for idx in 18..27 {
-            next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
+
+        // every way to put `left` tiles on the ranks of the first suit
+        fn suit_hands(cards: &mut Cards, rank: u8, left: u8) {
+            if rank == 9 {
+                if left == 0 {
+                    assert_same(cards);
+                }
+                return;
+            }
+            for x in 0..=left.min(4) {
+                for _ in 0..x {
+                    cards.insert(rank);
+                }
+                suit_hands(cards, rank + 1, left - x);
+                for _ in 0..x {
+                    cards.delete(rank);
+                }
+            }
         }
-        switch_suit(&mut f0, &mut f1);
-        for idx in 27..34 {
-            next_same_suit(&mut f0, &mut f1, &mut count, cards[idx] as i32);
-            switch_suit(&mut f0, &mut f1);
+
+        #[test]
+        fn table_matches_dp_on_random_hands() {
+            random_hands(0..34, 20_000);
+            // one suit and a few honors, so that winning hands are common
+            random_hands((9..18).chain(27..30), 20_000);
         }
 
-        let mut mx = 0;
-        for i in 0..3 {
-            for j in 0..3 {
-                mx = mx.max(f1[i][j]);
+        #[test]
+        fn table_matches_dp_on_single_suit_hands() {
+            // the suits share one table, the first stands for all of them
+            suit_hands(&mut Cards::default(), 0, 14);
+            // the rest of the hand is an honor pair or triplet
+            for honor in [2, 3] {
+                let mut cards = Cards::default();
+                for _ in 0..honor {
+                    cards.insert(27);
+                }
+                suit_hands(&mut cards, 0, 14 - honor);
             }
         }
-        return count >= 7 || mx >= 4;
     }
 }
@@ -1,19 +1,34 @@
 pub mod auth;
+pub mod bot;
 pub mod config;
 pub mod db;
 pub mod error;
 pub mod game;
 pub mod jwt;
+pub mod matchmaking;
 pub mod query_data;
 pub mod room;
+pub mod session;
 pub mod state;
+pub mod tournament;
 pub mod txmanager;
 pub mod ws;
 
 pub use auth::{handle_hello, handle_login, handle_register, jwt_auth};
 pub use db::init_db;
+pub use matchmaking::{handle_queue_join, handle_queue_leave, run_matchmaking};
 pub use query_data::{
-    handle_get_game_detail, handle_get_rankings, handle_get_round_detail, handle_get_username,
+    handle_get_game_chat, handle_get_game_detail, handle_get_metrics, handle_get_rankings,
+    handle_get_rating, handle_get_round_detail, handle_get_username,
+};
+pub use room::{
+    handle_room_bot, handle_room_join, handle_room_kick, handle_room_leave, handle_room_list,
+    handle_room_private, handle_room_public, handle_room_ready, handle_room_rules,
+    handle_room_seat, handle_room_seating, handle_room_spectate, handle_room_start,
+    handle_room_unready, handle_room_view, restore_games,
+};
+pub use tournament::{
+    handle_tournament_create, handle_tournament_register, handle_tournament_standings,
+    handle_tournament_start, handle_tournament_view,
 };
-pub use room::{handle_room_join, handle_room_leave, handle_room_start};
 pub use ws::handle_ws;
@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Extension, State};
+use axum::http;
+use axum::response::IntoResponse;
+use tokio::time::Instant;
+
+use crate::config::{
+    MATCH_BASE_TOLERANCE, MATCH_INTERVAL, MATCH_ROOM_BASE, MATCH_TOLERANCE_GROWTH,
+};
+use crate::db::query_rating;
+use crate::error::AppError;
+use crate::room::{Room, start_game};
+use crate::state::AppState;
+use crate::ws::ServerMessage;
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueueEntry {
+    pub uid: u64,
+    pub rating: f64,
+    pub joined_at: Instant,
+}
+
+impl QueueEntry {
+    // rating difference this player accepts, widening while they wait
+    fn tolerance(&self, now: Instant) -> f64 {
+        let waited = now.duration_since(self.joined_at).as_secs_f64();
+        return MATCH_BASE_TOLERANCE + MATCH_TOLERANCE_GROWTH * waited;
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct MatchQueue {
+    pub entries: Vec<QueueEntry>,
+}
+
+impl MatchQueue {
+    pub fn contains(&self, uid: u64) -> bool {
+        return self.entries.iter().any(|entry| entry.uid == uid);
+    }
+
+    pub fn remove(&mut self, uid: u64) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.uid != uid);
+        return self.entries.len() != len;
+    }
+
+    // take groups of four players with close ratings out of the queue
+    fn take_groups(&mut self, now: Instant) -> Vec<[QueueEntry; 4]> {
+        self.entries
+            .sort_by(|a, b| a.rating.partial_cmp(&b.rating).unwrap());
+
+        let mut groups = Vec::new();
+        let mut rest = Vec::with_capacity(self.entries.len());
+        let mut i = 0;
+        while i < self.entries.len() {
+            if i + 4 <= self.entries.len() {
+                let group: [QueueEntry; 4] = self.entries[i..i + 4].try_into().unwrap();
+                let spread = group[3].rating - group[0].rating;
+                let tolerance = group
+                    .iter()
+                    .map(|entry| entry.tolerance(now))
+                    .fold(0.0, f64::max);
+                if spread <= tolerance {
+                    groups.push(group);
+                    i += 4;
+                    continue;
+                }
+            }
+            rest.push(self.entries[i]);
+            i += 1;
+        }
+        self.entries = rest;
+        return groups;
+    }
+}
+
+async fn queue_join(state: &AppState, uid: u64) -> Result<(), AppError> {
+    {
+        let hall = state.hall.read().await;
+        if let Some(&room_id) = hall.belongs.get(&uid).or(hall.spectating.get(&uid)) {
+            return Err(AppError::UserAlreadyInRoom(room_id));
+        }
+    }
+    let rating = query_rating(&state.db_pool, uid).await?;
+
+    let mut queue = state.queue.write().await;
+    if queue.contains(uid) {
+        return Err(AppError::UserAlreadyInQueue);
+    }
+    queue.entries.push(QueueEntry {
+        uid,
+        rating,
+        joined_at: Instant::now(),
+    });
+    return Ok(());
+}
+
+async fn queue_leave(state: &AppState, uid: u64) -> Result<(), AppError> {
+    let mut queue = state.queue.write().await;
+    if queue.remove(uid) {
+        return Ok(());
+    } else {
+        return Err(AppError::UserNotInQueue);
+    }
+}
+
+async fn create_match(state: &AppState, group: [QueueEntry; 4]) {
+    let mut hall = state.hall.write().await;
+
+    // players may have joined a room since they were queued
+    let busy: Vec<u64> = group
+        .iter()
+        .map(|entry| entry.uid)
+        .filter(|uid| hall.belongs.contains_key(uid) || hall.spectating.contains_key(uid))
+        .collect();
+    if busy.len() != 0 {
+        drop(hall);
+        let mut queue = state.queue.write().await;
+        for entry in group {
+            if !busy.contains(&entry.uid) {
+                queue.entries.push(entry);
+            }
+        }
+        return;
+    }
+
+    let mut room_id = MATCH_ROOM_BASE;
+    while hall.rooms.contains_key(&room_id) {
+        room_id += 1;
+    }
+
+    let mut room = Room {
+        owner: group[0].uid,
+        matchmade: true,
+        ..Default::default()
+    };
+    for entry in group {
+        room.players.push(entry.uid);
+        room.ready.insert(entry.uid);
+        hall.belongs.insert(entry.uid, room_id);
+    }
+    hall.rooms.insert(room_id, room);
+    tracing::info!(
+        "matched {:?} into room {}",
+        group.map(|entry| entry.uid),
+        room_id
+    );
+
+    {
+        let tx2clients = &state.tx2clients;
+        for entry in group {
+            match tx2clients.send(&entry.uid, ServerMessage::MatchFound(room_id)) {
+                Err(AppError::TxNotExist) | Ok(_) => (),
+                Err(e) => tracing::error!("{:?}", e),
+            }
+        }
+    }
+
+    let room = hall.rooms.get_mut(&room_id).unwrap();
+    if let Err(e) = start_game(state, room_id, room).await {
+        tracing::error!("{:?}", e);
+    }
+}
+
+pub async fn run_matchmaking(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(MATCH_INTERVAL));
+    loop {
+        interval.tick().await;
+        let groups = state.queue.write().await.take_groups(Instant::now());
+        for group in groups {
+            create_match(&state, group).await;
+        }
+    }
+}
+
+pub async fn handle_queue_join(
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match queue_join(&state, uid).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::UserAlreadyInRoom(room_id)) => {
+            return (
+                http::StatusCode::CONFLICT,
+                format!("user already in room {}", room_id),
+            )
+                .into_response();
+        }
+        Err(AppError::UserAlreadyInQueue) => {
+            return (http::StatusCode::CONFLICT, "user already in queue").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_queue_leave(
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match queue_leave(&state, uid).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::UserNotInQueue) => {
+            return (http::StatusCode::CONFLICT, "user not in queue").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
@@ -6,9 +6,13 @@ use axum::{
 use deadpool_sqlite::Pool;
 use serde::Serialize;
 
-use crate::db::{query_game_detail, query_rankings, query_round_detail, query_username};
+use crate::db::{
+    query_game_chat, query_game_detail, query_rankings, query_rating, query_round_detail,
+    query_username,
+};
 use crate::error::AppError;
 use crate::state::AppState;
+use crate::txmanager::QueueMetrics;
 
 #[derive(Serialize)]
 pub struct GameDetail {
@@ -59,6 +63,12 @@ async fn get_game_detail(db_pool: &Pool, game_id: usize) -> Result<String, AppEr
     return Ok(res);
 }
 
+async fn get_game_chat(db_pool: &Pool, game_id: usize) -> Result<String, AppError> {
+    let chat = query_game_chat(db_pool, game_id).await?;
+    let res = serde_json::to_string(&chat)?;
+    return Ok(res);
+}
+
 async fn get_round_detail(
     db_pool: &Pool,
     game_id: usize,
@@ -74,6 +84,26 @@ async fn get_username(db_pool: &Pool, uid: u64) -> Result<String, AppError> {
     return Ok(res);
 }
 
+async fn get_rating(db_pool: &Pool, uid: u64) -> Result<String, AppError> {
+    let rating = query_rating(db_pool, uid).await?;
+    return Ok(rating.to_string());
+}
+
+#[derive(Serialize)]
+pub struct Metrics {
+    pub clients: QueueMetrics,
+    pub games: QueueMetrics,
+}
+
+async fn get_metrics(state: &AppState) -> Result<String, AppError> {
+    let metrics = Metrics {
+        clients: state.tx2clients.metrics(),
+        games: state.tx2games.metrics(),
+    };
+    let res = serde_json::to_string(&metrics)?;
+    return Ok(res);
+}
+
 pub async fn handle_get_rankings(
     Path(game_id): Path<usize>,
     State(state): State<AppState>,
@@ -102,6 +132,20 @@ pub async fn handle_get_game_detail(
     }
 }
 
+pub async fn handle_get_game_chat(
+    Path(game_id): Path<usize>,
+    State(state): State<AppState>,
+) -> Response {
+    match get_game_chat(&state.db_pool, game_id).await {
+        Ok(res) => return res.into_response(),
+        Err(AppError::GameNotExist) => return http::StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
 pub async fn handle_get_round_detail(
     Path((game_id, round_id)): Path<(usize, usize)>,
     State(state): State<AppState>,
@@ -129,3 +173,23 @@ pub async fn handle_get_username(Path(uid): Path<u64>, State(state): State<AppSt
         }
     }
 }
+
+pub async fn handle_get_rating(Path(uid): Path<u64>, State(state): State<AppState>) -> Response {
+    match get_rating(&state.db_pool, uid).await {
+        Ok(res) => return res.into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_get_metrics(State(state): State<AppState>) -> Response {
+    match get_metrics(&state).await {
+        Ok(res) => return res.into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
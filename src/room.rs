@@ -1,111 +1,1249 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use axum::body::Body;
-use axum::extract::{Extension, Path, State};
+use axum::extract::rejection::FormRejection;
+use axum::extract::{Extension, Form, Path, Query, State};
 use axum::http;
-use axum::response::IntoResponse;
-use tokio::sync::mpsc;
+use axum::response::{IntoResponse, Response};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 
-use crate::db::add_game;
+use crate::auth::hash_password;
+use crate::bot::{StrategyKind, run_bot};
+use crate::config::{CHAT_MAX_LEN, MAX_SPECTATOR_DELAY, MAX_TURN_TIME_LIMIT, REMATCH_TIMEOUT};
+use crate::db::{
+    add_bot, add_game, delete_running_game, query_bots, query_running_games, query_username,
+    save_running_game, update_ratings,
+};
 use crate::error::AppError;
-use crate::game::Game;
+use crate::game::{ChatRecord, Game, GameMessage, GameState};
 use crate::state::AppState;
-use crate::ws::{ClientMessage, ServerMessage};
+use crate::tournament::game_finished;
+use crate::ws::ServerMessage;
+
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RoomRules {
+    // reveal all hands to spectators after each round
+    pub spectator_open_hands: bool,
+    // seconds
+    pub spectator_delay: u64,
+    // let spectators read and send chat
+    pub spectator_chat: bool,
+    // save the chat transcript of the game with its history
+    pub save_chat: bool,
+    // seconds before an idle player discards their draw, 0 means no limit
+    pub turn_time_limit: u64,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeatingPolicy {
+    #[default]
+    Random,
+    JoinOrder,
+    Explicit,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomStatus {
+    #[default]
+    Waiting,
+    Playing,
+    // results are shown and members vote for a rematch
+    PostGame,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GameResult {
+    pub game_id: Option<usize>,
+    pub players: [u64; 4],
+    pub players_score: [i64; 4],
+}
+
+// summary of a public room pushed to lobby subscribers
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LobbyRoom {
+    pub room_id: usize,
+    pub owner: u64,
+    pub occupancy: usize,
+    pub status: RoomStatus,
+    pub rules: RoomRules,
+}
+
+impl LobbyRoom {
+    fn new(room_id: usize, room: &Room) -> LobbyRoom {
+        return LobbyRoom {
+            room_id,
+            owner: room.owner,
+            occupancy: room.players.len(),
+            status: room.status,
+            rules: room.rules,
+        };
+    }
+}
+
+// a running game with the room around it, kept in the database until it ends
+#[derive(Serialize, Deserialize)]
+pub struct SavedGame {
+    pub owner: u64,
+    pub players: Vec<u64>,
+    pub tournament: Option<u64>,
+    #[serde(default)]
+    pub matchmade: bool,
+    // strategies of the bots, games saved without them restore the default
+    #[serde(default)]
+    pub strategies: HashMap<u64, StrategyKind>,
+    #[serde(default)]
+    pub privacy: Privacy,
+    #[serde(default)]
+    pub seating: SeatingPolicy,
+    #[serde(default)]
+    pub seats: [Option<u64>; 4],
+    pub games: usize,
+    pub game: GameState,
+}
+
+impl SavedGame {
+    fn new(room: &Room, game: GameState) -> SavedGame {
+        return SavedGame {
+            owner: room.owner,
+            players: room.players.clone(),
+            tournament: room.tournament,
+            matchmade: room.matchmade,
+            strategies: room.strategies.clone(),
+            privacy: room.privacy.clone(),
+            seating: room.seating,
+            seats: room.seats,
+            games: room.games,
+            game,
+        };
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub enum Privacy {
+    #[default]
+    Public,
+    // salted hash of the room password
+    Password(String),
+    InviteCode(String),
+}
+
+#[derive(Default, Debug)]
+pub struct Room {
+    // the only member allowed to change settings, passed on when they leave
+    pub owner: u64,
+    // in join order
+    pub players: Vec<u64>,
+    pub ready: HashSet<u64>,
+    pub rules: RoomRules,
+    pub seating: SeatingPolicy,
+    // seats chosen by players under `SeatingPolicy::Explicit`
+    pub seats: [Option<u64>; 4],
+    // seat map and random seed of the last started game
+    pub seat_map: Option<[u64; 4]>,
+    pub seat_seed: Option<u64>,
+    pub privacy: Privacy,
+    pub spectators: HashSet<u64>,
+    // server-side bot players, always ready
+    pub bots: HashSet<u64>,
+    pub strategies: HashMap<u64, StrategyKind>,
+    pub status: RoomStatus,
+    // number of games started, tells a stale rematch timeout apart
+    pub games: usize,
+    pub last_result: Option<GameResult>,
+    // members agreeing to a rematch
+    pub rematch: HashSet<u64>,
+    // seats for the next game, overriding the seating policy once
+    pub next_seats: Option<[u64; 4]>,
+    // set for tables created by a tournament
+    pub tournament: Option<u64>,
+    // set for rooms created by matchmaking, only their games are rated
+    pub matchmade: bool,
+}
+
+impl Room {
+    pub fn is_private(&self) -> bool {
+        return !matches!(self.privacy, Privacy::Public);
+    }
+
+    fn check_access(&self, params: &RoomJoinParams) -> Result<(), AppError> {
+        match &self.privacy {
+            Privacy::Public => return Ok(()),
+            Privacy::Password(passhash) => match &params.password {
+                Some(password) if hash_password(password) == *passhash => return Ok(()),
+                _ => return Err(AppError::RoomPasswordIncorrect),
+            },
+            Privacy::InviteCode(code) => {
+                if params.code.as_ref() == Some(code) {
+                    return Ok(());
+                } else {
+                    return Err(AppError::InviteCodeIncorrect);
+                }
+            }
+        }
+    }
+
+    pub fn all_ready(&self) -> bool {
+        return self.players.iter().all(|uid| self.ready.contains(uid));
+    }
+
+    pub fn can_start(&self) -> bool {
+        return self.players.len() == 4
+            && self.all_ready()
+            && (self.seating != SeatingPolicy::Explicit || self.seats.iter().all(Option::is_some));
+    }
+
+    fn remove_player(&mut self, uid: u64) {
+        self.players.retain(|&i| i != uid);
+        self.ready.remove(&uid);
+        if self.owner == uid {
+            // bots never own a room
+            if let Some(&next) = self.players.iter().find(|uid| !self.bots.contains(uid)) {
+                self.owner = next;
+            }
+        }
+        for seat in self.seats.iter_mut() {
+            if *seat == Some(uid) {
+                *seat = None;
+            }
+        }
+    }
+
+    fn draw_seats(&mut self) -> Result<[u64; 4], AppError> {
+        let mut players = self.players.clone();
+        self.seat_seed = None;
+        if let Some(seats) = self.next_seats.take() {
+            self.seat_map = Some(seats);
+            return Ok(seats);
+        }
+        match self.seating {
+            SeatingPolicy::Random => {
+                let seed = rand::random();
+                players.shuffle(&mut StdRng::seed_from_u64(seed));
+                self.seat_seed = Some(seed);
+            }
+            SeatingPolicy::JoinOrder => (),
+            SeatingPolicy::Explicit => {
+                players = self.seats.iter().flatten().copied().collect();
+            }
+        }
+        let seat_map: [u64; 4] = players.try_into().map_err(|_| AppError::SeatNotChosen)?;
+        self.seat_map = Some(seat_map);
+        return Ok(seat_map);
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct Hall {
-    pub rooms: HashMap<usize, HashSet<u64>>,
+    pub rooms: HashMap<usize, Room>,
     pub belongs: HashMap<u64, usize>,
+    pub spectating: HashMap<u64, usize>,
 }
 
-async fn room_join(state: &AppState, room_id: usize, uid: u64) -> Result<(), AppError> {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomMember {
+    pub uid: u64,
+    pub username: String,
+    pub ready: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomView {
+    pub room_id: usize,
+    pub owner: u64,
+    pub members: Vec<RoomMember>,
+    pub occupancy: usize,
+    pub playing: bool,
+    pub status: RoomStatus,
+    pub last_result: Option<GameResult>,
+    pub rules: RoomRules,
+    pub seating: SeatingPolicy,
+    pub seats: [Option<u64>; 4],
+    pub seat_map: Option<[u64; 4]>,
+    pub seat_seed: Option<u64>,
+    pub private: bool,
+    // only shown to members
+    pub invite_code: Option<String>,
+    pub spectators: usize,
+}
+
+impl RoomView {
+    // usernames are left empty and filled by `fill_usernames`
+    fn new(room_id: usize, room: &Room, playing: bool) -> RoomView {
+        let members = room
+            .players
+            .iter()
+            .map(|&uid| RoomMember {
+                uid,
+                username: String::new(),
+                ready: room.ready.contains(&uid),
+            })
+            .collect();
+        return RoomView {
+            room_id,
+            owner: room.owner,
+            members,
+            occupancy: room.players.len(),
+            playing,
+            status: room.status,
+            last_result: room.last_result,
+            rules: room.rules,
+            seating: room.seating,
+            seats: room.seats,
+            seat_map: room.seat_map,
+            seat_seed: room.seat_seed,
+            private: room.is_private(),
+            invite_code: None,
+            spectators: room.spectators.len(),
+        };
+    }
+
+    async fn fill_usernames(&mut self, state: &AppState) -> Result<(), AppError> {
+        for member in self.members.iter_mut() {
+            member.username = query_username(&state.db_pool, member.uid).await?;
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RoomListParams {
+    #[serde(default)]
+    joinable: bool,
+}
+
+#[derive(Default, Deserialize)]
+pub struct RoomJoinParams {
+    password: Option<String>,
+    code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RoomPrivateParams {
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RoomRulesParams {
+    spectator_open_hands: Option<bool>,
+    spectator_delay: Option<u64>,
+    spectator_chat: Option<bool>,
+    save_chat: Option<bool>,
+    turn_time_limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct RoomBotParams {
+    #[serde(default)]
+    strategy: StrategyKind,
+}
+
+async fn room_join(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    params: RoomJoinParams,
+) -> Result<(), AppError> {
     let mut hall = state.hall.write().await;
-    if hall.belongs.contains_key(&uid) {
-        return Err(AppError::UserAlreadyInRoom(hall.belongs[&uid]));
+    if let Some(&room_id) = hall.belongs.get(&uid).or(hall.spectating.get(&uid)) {
+        return Err(AppError::UserAlreadyInRoom(room_id));
     } else {
-        if let Some(players) = hall.rooms.get_mut(&room_id) {
-            if players.len() < 4 {
-                players.insert(uid);
+        if let Some(room) = hall.rooms.get_mut(&room_id) {
+            room.check_access(&params)?;
+            if room.players.len() < 4 {
+                room.players.push(uid);
+                notify_room(state, room, ServerMessage::MemberJoined(uid)).await;
+                notify_lobby(state, room_id, Some(room)).await;
                 hall.belongs.insert(uid, room_id);
+                route(state, uid, Some(room_id));
+                // a member of a room stops waiting for a match
+                state.queue.write().await.remove(uid);
                 return Ok(());
             } else {
                 return Err(AppError::RoomAlreadyFull);
             }
-        } else {
-            let mut players = HashSet::with_capacity(4);
-            players.insert(uid);
-            hall.belongs.insert(uid, room_id);
-            hall.rooms.insert(room_id, players);
-            return Ok(());
+        } else {
+            let room = Room {
+                owner: uid,
+                players: vec![uid],
+                ..Default::default()
+            };
+            notify_lobby(state, room_id, Some(&room)).await;
+            hall.belongs.insert(uid, room_id);
+            hall.rooms.insert(room_id, room);
+            state.queue.write().await.remove(uid);
+            return Ok(());
+        }
+    }
+}
+
+async fn room_leave(state: &AppState, room_id: usize, uid: u64) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if hall.spectating.get(&uid) == Some(&room_id) {
+        hall.spectating.remove(&uid);
+        route(state, uid, None);
+        hall.rooms
+            .get_mut(&room_id)
+            .unwrap()
+            .spectators
+            .remove(&uid);
+        let tx2games = &state.tx2games;
+        match tx2games.send(&room_id, GameMessage::Unspectate(uid)) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+        return Ok(());
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if state.tx2games.contains(&room_id) {
+        // the seat stays taken until the game ends, a disconnected player is auto-played
+        return Err(AppError::GameAlreadyStart);
+    } else {
+        notify_room(state, &hall.rooms[&room_id], ServerMessage::MemberLeft(uid)).await;
+        remove_member(state, &mut hall, room_id, uid).await;
+        return Ok(());
+    }
+}
+
+async fn room_kick(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    target: u64,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if hall.rooms[&room_id].owner != uid {
+        return Err(AppError::NotRoomOwner);
+    } else if target == uid {
+        return Err(AppError::CannotKickSelf);
+    } else if hall.belongs.get(&target) != Some(&room_id) {
+        return Err(AppError::UserNotInRoom);
+    } else if state.tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    }
+    notify_room(
+        state,
+        &hall.rooms[&room_id],
+        ServerMessage::MemberKicked(target),
+    )
+    .await;
+    remove_member(state, &mut hall, room_id, target).await;
+    return Ok(());
+}
+
+// removes a player or bot, closing the room once no human is left
+async fn remove_member(state: &AppState, hall: &mut Hall, room_id: usize, uid: u64) {
+    hall.belongs.remove(&uid);
+    route(state, uid, None);
+    let room = hall.rooms.get_mut(&room_id).unwrap();
+    room.remove_player(uid);
+    if room.bots.remove(&uid) {
+        room.strategies.remove(&uid);
+        state.tx2clients.delete(&uid);
+    }
+    if room.players.iter().all(|uid| room.bots.contains(uid)) {
+        // bot tasks end once their channels are dropped
+        let bots = std::mem::take(&mut room.bots);
+        let tx2clients = &state.tx2clients;
+        for bot in bots {
+            hall.belongs.remove(&bot);
+            tx2clients.delete(&bot);
+        }
+        // spectators and the last human are told, nobody else is left
+        let spectators = std::mem::take(&mut room.spectators);
+        for spectator in spectators.iter() {
+            hall.spectating.remove(spectator);
+            route(state, *spectator, None);
+            match tx2clients.send(spectator, ServerMessage::RoomClosed(room_id)) {
+                Err(AppError::TxNotExist) | Ok(_) => (),
+                Err(e) => tracing::error!("{:?}", e),
+            }
+        }
+        match tx2clients.send(&uid, ServerMessage::RoomClosed(room_id)) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+        hall.rooms.remove(&room_id);
+    }
+    notify_lobby(state, room_id, hall.rooms.get(&room_id)).await;
+}
+
+// points `uid` at the game running in `room_id`, or at nothing
+fn route(state: &AppState, uid: u64, room_id: Option<usize>) {
+    match room_id.and_then(|room_id| state.tx2games.get(&room_id)) {
+        Some(tx) => {
+            state.routes.replace(uid, tx);
+        }
+        None => {
+            state.routes.delete(&uid);
+        }
+    }
+}
+
+/// Takes the user out of the room they play in or watch, if any.
+pub(crate) async fn leave_hall(state: &AppState, hall: &mut Hall, uid: u64) {
+    if let Some(room_id) = hall.spectating.remove(&uid) {
+        route(state, uid, None);
+        if let Some(room) = hall.rooms.get_mut(&room_id) {
+            room.spectators.remove(&uid);
+        }
+        let tx2games = &state.tx2games;
+        match tx2games.send(&room_id, GameMessage::Unspectate(uid)) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    }
+    if let Some(&room_id) = hall.belongs.get(&uid) {
+        notify_room(state, &hall.rooms[&room_id], ServerMessage::MemberLeft(uid)).await;
+        remove_member(state, hall, room_id, uid).await;
+    }
+}
+
+async fn notify_lobby(state: &AppState, room_id: usize, room: Option<&Room>) {
+    let msg = match room {
+        Some(room) if !room.is_private() => {
+            ServerMessage::LobbyUpdate(LobbyRoom::new(room_id, room))
+        }
+        _ => ServerMessage::LobbyRemove(room_id),
+    };
+    let lobby = state.lobby.read().await;
+    let tx2clients = &state.tx2clients;
+    for uid in lobby.iter() {
+        match tx2clients.send(uid, msg.clone()) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    }
+}
+
+pub(crate) async fn lobby_subscribe(state: &AppState, uid: u64) -> Result<(), AppError> {
+    let hall = state.hall.read().await;
+    let mut lobby = state.lobby.write().await;
+    let tx2clients = &state.tx2clients;
+    lobby.insert(uid);
+    // start with a snapshot of the public rooms
+    for (&room_id, room) in hall.rooms.iter() {
+        if !room.is_private() {
+            tx2clients.send(
+                &uid,
+                ServerMessage::LobbyUpdate(LobbyRoom::new(room_id, room)),
+            )?;
+        }
+    }
+    return Ok(());
+}
+
+pub(crate) async fn lobby_unsubscribe(state: &AppState, uid: u64) {
+    state.lobby.write().await.remove(&uid);
+}
+
+async fn notify_room(state: &AppState, room: &Room, msg: ServerMessage) {
+    let tx2clients = &state.tx2clients;
+    for uid in room.players.iter() {
+        match tx2clients.send(uid, msg.clone()) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    }
+}
+
+pub(crate) async fn room_chat(state: &AppState, uid: u64, text: String) -> Result<(), AppError> {
+    let text = text.trim().to_string();
+    if text.len() == 0 {
+        return Ok(());
+    } else if text.chars().count() > CHAT_MAX_LEN {
+        return Err(AppError::ChatTooLong);
+    }
+
+    let hall = state.hall.read().await;
+    let (room_id, is_spectator) = match hall.belongs.get(&uid) {
+        Some(&room_id) => (room_id, false),
+        None => match hall.spectating.get(&uid) {
+            Some(&room_id) => (room_id, true),
+            None => return Err(AppError::UserNotInRoom),
+        },
+    };
+    let room = &hall.rooms[&room_id];
+    if is_spectator && !room.rules.spectator_chat {
+        return Err(AppError::ChatNotAllowed);
+    }
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let msg = ServerMessage::Chat {
+        uid,
+        text: text.clone(),
+        ts,
+    };
+    notify_room(state, room, msg.clone()).await;
+    if room.rules.spectator_chat {
+        let tx2clients = &state.tx2clients;
+        for uid in room.spectators.iter() {
+            match tx2clients.send(uid, msg.clone()) {
+                Err(AppError::TxNotExist) | Ok(_) => (),
+                Err(e) => tracing::error!("{:?}", e),
+            }
+        }
+    }
+
+    // the game records chat only while it is running
+    if room.rules.save_chat {
+        let tx2games = &state.tx2games;
+        match tx2games.send(&room_id, GameMessage::Chat(ChatRecord { uid, text, ts })) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    }
+    return Ok(());
+}
+
+pub(crate) async fn start_game(
+    state: &AppState,
+    room_id: usize,
+    room: &mut Room,
+) -> Result<(), AppError> {
+    let tx2games = &state.tx2games;
+    if tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    }
+
+    let players = room.draw_seats()?;
+    tracing::info!(
+        "room {} seats {:?} drawn by {:?}, seed {:?}",
+        room_id,
+        players,
+        room.seating,
+        room.seat_seed
+    );
+    let rules = room.rules;
+    let bots = room.bots.clone();
+    room.ready.retain(|uid| bots.contains(uid));
+    room.rematch.clear();
+    room.status = RoomStatus::Playing;
+    room.games += 1;
+    notify_lobby(state, room_id, Some(room)).await;
+
+    let game = Game::new(players, bots, rules, state.tx2clients.clone());
+    spawn_game(state, room_id, room, game);
+    return Ok(());
+}
+
+// runs the game of a room and wraps the room up after it ends
+fn spawn_game(state: &AppState, room_id: usize, room: &Room, mut game: Game) {
+    let games = room.games;
+    let matchmade = room.matchmade;
+    let mut saved = SavedGame::new(room, game.save());
+    let mut snapshots = game.watch();
+    let db_pool = state.db_pool.clone();
+    let hall = state.hall.clone();
+    let saver = tokio::spawn(async move {
+        // only the latest state is written, a slow database skips some
+        loop {
+            let game = snapshots.borrow_and_update().clone();
+            // the owner may make the room private or move seats while it plays
+            match hall.read().await.rooms.get(&room_id) {
+                Some(room) if room.games == games => saved = SavedGame::new(room, game),
+                _ => saved.game = game,
+            }
+            if let Err(e) = save_running_game(&db_pool, room_id, &saved).await {
+                tracing::error!("{:?}", e);
+            }
+            if snapshots.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let tx2games = &state.tx2games;
+    let (tx, rx) = tx2games.channel();
+    let _state = state.clone();
+    let _tx = tx.clone();
+    tokio::spawn(async move {
+        let state = _state;
+        let tx = _tx;
+
+        game.run(rx).await;
+        // the last state has to be written before `add_game` drops it
+        if let Err(e) = saver.await {
+            tracing::error!("{:?}", e);
+        }
+
+        let game = Arc::new(game);
+        let game_id = match add_game(&state.db_pool, room_id, game.clone()).await {
+            Ok(game_id) => {
+                game.announce(ServerMessage::GameEnd(game_id)).await;
+                Some(game_id)
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                // a finished game must not be restored on the next start
+                if let Err(e) = delete_running_game(&state.db_pool, room_id).await {
+                    tracing::error!("{:?}", e);
+                }
+                None
+            }
+        };
+        if matchmade
+            && let Err(e) = update_ratings(&state.db_pool, game.players, game.players_score).await
+        {
+            tracing::error!("{:?}", e);
+        }
+
+        // everyone whose route may still point at this game
+        let mut members: HashSet<u64> = game.players.into_iter().collect();
+        members.extend(game.spectators.iter().copied());
+
+        // spectators only watch a running game
+        let mut hall = state.hall.write().await;
+        let spectators = match hall.rooms.get_mut(&room_id) {
+            Some(room) if room.games == games => std::mem::take(&mut room.spectators),
+            _ => HashSet::new(),
+        };
+        for uid in spectators {
+            hall.spectating.remove(&uid);
+            members.insert(uid);
+        }
+
+        let mut tournament = None;
+        // a room of the same id created meanwhile is left alone
+        if let Some(room) = hall.rooms.get_mut(&room_id)
+            && room.games == games
+        {
+            members.extend(room.players.iter().copied());
+            let result = GameResult {
+                game_id,
+                players: game.players,
+                players_score: game.players_score,
+            };
+            room.last_result = Some(result);
+            notify_room(&state, room, ServerMessage::GameResult(result)).await;
+            if room.tournament.is_some() {
+                // tournament tables are dissolved, the next round seats everyone again
+                tournament = room.tournament;
+                let room = hall.rooms.remove(&room_id).unwrap();
+                for uid in room.players {
+                    hall.belongs.remove(&uid);
+                }
+                notify_lobby(&state, room_id, None).await;
+            } else {
+                room.status = RoomStatus::PostGame;
+                // bots always agree
+                room.rematch = room.bots.clone();
+                notify_lobby(&state, room_id, Some(room)).await;
+                spawn_rematch_timeout(state.clone(), room_id, games);
+            }
+        }
+
+        let tx2games = &state.tx2games;
+        tx2games.delete_if_same(&room_id, &tx);
+        // members may have moved on to another game already
+        for uid in members.iter() {
+            state.routes.delete_if_same(uid, &tx);
+        }
+        drop(hall);
+
+        if let Some(tournament_id) = tournament
+            && let Err(e) = game_finished(state.clone(), tournament_id, room_id, game_id).await
+        {
+            tracing::error!("{:?}", e);
+        }
+    });
+
+    for &uid in room.players.iter().chain(room.spectators.iter()) {
+        state.routes.replace(uid, tx.clone());
+    }
+    tx2games.insert(room_id, tx);
+}
+
+/// Re-creates the rooms of games that were running when the server stopped
+/// and continues the games, players get them back by reconnecting.
+pub async fn restore_games(state: &AppState) -> Result<(), AppError> {
+    let saved_games = query_running_games(&state.db_pool).await?;
+    let mut hall = state.hall.write().await;
+    for (room_id, saved) in saved_games {
+        let bots = saved.game.bots.clone();
+        let room = Room {
+            owner: saved.owner,
+            players: saved.players,
+            ready: bots.clone(),
+            rules: saved.game.rules,
+            seat_map: Some(saved.game.players),
+            bots: bots.clone(),
+            status: RoomStatus::Playing,
+            games: saved.games,
+            tournament: saved.tournament,
+            matchmade: saved.matchmade,
+            strategies: saved.strategies,
+            privacy: saved.privacy,
+            seating: saved.seating,
+            seats: saved.seats,
+            ..Default::default()
+        };
+        for &uid in room.players.iter() {
+            hall.belongs.insert(uid, room_id);
+        }
+
+        let tx2clients = &state.tx2clients;
+        for bot in bots {
+            let (tx, rx) = tx2clients.channel();
+            tx2clients.replace(bot, tx);
+            let strategy = room.strategies.get(&bot).copied().unwrap_or_default();
+            let strategy = strategy.build();
+            tokio::spawn(run_bot(state.clone(), room_id, bot, strategy, rx));
+        }
+
+        let game = Game::restore(saved.game, state.tx2clients.clone());
+        spawn_game(state, room_id, &room, game);
+        hall.rooms.insert(room_id, room);
+        tracing::info!("restored the game of room {}", room_id);
+    }
+    return Ok(());
+}
+
+fn spawn_rematch_timeout(state: AppState, room_id: usize, games: usize) {
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(REMATCH_TIMEOUT)).await;
+
+        let mut hall = state.hall.write().await;
+        let room = match hall.rooms.get_mut(&room_id) {
+            Some(room) if room.status == RoomStatus::PostGame && room.games == games => room,
+            _ => return,
+        };
+        // members who did not vote are considered away
+        let away: Vec<u64> = room
+            .players
+            .iter()
+            .copied()
+            .filter(|uid| !room.rematch.contains(uid))
+            .collect();
+        for uid in away {
+            tracing::info!("remove {} from room {} after rematch timeout", uid, room_id);
+            notify_room(
+                &state,
+                &hall.rooms[&room_id],
+                ServerMessage::MemberLeft(uid),
+            )
+            .await;
+            remove_member(&state, &mut hall, room_id, uid).await;
+        }
+        if let Some(room) = hall.rooms.get_mut(&room_id)
+            && let Err(e) = rematch(&state, room_id, room).await
+        {
+            tracing::error!("{:?}", e);
+        }
+    });
+}
+
+// restarts with seats rotated by one if everyone is still here, otherwise waits for new members
+async fn rematch(state: &AppState, room_id: usize, room: &mut Room) -> Result<(), AppError> {
+    room.rematch.clear();
+    match room.seat_map {
+        Some(seat_map)
+            if room.players.len() == 4 && seat_map.iter().all(|uid| room.players.contains(uid)) =>
+        {
+            room.next_seats = Some([seat_map[1], seat_map[2], seat_map[3], seat_map[0]]);
+            return start_game(state, room_id, room).await;
+        }
+        _ => {
+            room.status = RoomStatus::Waiting;
+            notify_lobby(state, room_id, Some(room)).await;
+            return Ok(());
+        }
+    }
+}
+
+pub(crate) async fn room_rematch_vote(
+    state: &AppState,
+    uid: u64,
+    agree: bool,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    let room_id = *hall.belongs.get(&uid).ok_or(AppError::UserNotInRoom)?;
+    let room = hall.rooms.get_mut(&room_id).unwrap();
+    if room.status != RoomStatus::PostGame {
+        return Err(AppError::RematchNotOpen);
+    }
+    notify_room(state, room, ServerMessage::RematchVote((uid, agree))).await;
+
+    if !agree {
+        notify_room(state, room, ServerMessage::MemberLeft(uid)).await;
+        remove_member(state, &mut hall, room_id, uid).await;
+        if let Some(room) = hall.rooms.get_mut(&room_id) {
+            room.rematch.clear();
+            room.status = RoomStatus::Waiting;
+            notify_lobby(state, room_id, Some(room)).await;
+        }
+        return Ok(());
+    }
+    room.rematch.insert(uid);
+    if room.players.iter().all(|uid| room.rematch.contains(uid)) {
+        return rematch(state, room_id, room).await;
+    }
+    return Ok(());
+}
+
+async fn room_start(state: &AppState, room_id: usize, uid: u64) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if hall.rooms[&room_id].players.len() != 4 {
+        return Err(AppError::RoomNotFull);
+    } else if !hall.rooms[&room_id].all_ready() {
+        return Err(AppError::RoomNotReady);
+    } else {
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        return start_game(state, room_id, room).await;
+    }
+}
+
+async fn room_ready(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    ready: bool,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if state.tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    } else {
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        if ready {
+            room.ready.insert(uid);
+        } else {
+            room.ready.remove(&uid);
+        }
+        notify_room(state, room, ServerMessage::ReadyState((uid, ready))).await;
+
+        // start automatically once every seat is ready
+        if room.can_start() {
+            return start_game(state, room_id, room).await;
+        }
+        return Ok(());
+    }
+}
+
+async fn room_seating(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    seating: SeatingPolicy,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if hall.rooms[&room_id].owner != uid {
+        return Err(AppError::NotRoomOwner);
+    } else if state.tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    } else {
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        room.seating = seating;
+        if room.can_start() {
+            return start_game(state, room_id, room).await;
+        }
+        return Ok(());
+    }
+}
+
+async fn room_seat(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    seat: usize,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if seat >= 4 {
+        return Err(AppError::SeatNotExist);
+    } else if state.tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    } else {
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        match room.seats[seat] {
+            Some(i) if i == uid => return Ok(()),
+            Some(_) => return Err(AppError::SeatTaken),
+            None => {
+                for i in room.seats.iter_mut() {
+                    if *i == Some(uid) {
+                        *i = None;
+                    }
+                }
+                room.seats[seat] = Some(uid);
+                if room.can_start() {
+                    return start_game(state, room_id, room).await;
+                }
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn check_add_bot(state: &AppState, hall: &Hall, room_id: usize, uid: u64) -> Result<(), AppError> {
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if hall.rooms[&room_id].owner != uid {
+        return Err(AppError::NotRoomOwner);
+    } else if hall.rooms[&room_id].players.len() >= 4 {
+        return Err(AppError::RoomAlreadyFull);
+    } else if state.tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    }
+    return Ok(());
+}
+
+async fn room_add_bot(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    strategy: StrategyKind,
+) -> Result<(), AppError> {
+    // the database is only used without the hall, nothing else waits on it
+    check_add_bot(state, &*state.hall.read().await, room_id, uid)?;
+    let bots = query_bots(&state.db_pool).await?;
+
+    let mut hall = state.hall.write().await;
+    check_add_bot(state, &hall, room_id, uid)?;
+    // reuse an idle bot account before creating a new one
+    let idle = bots.into_iter().find(|bot| !hall.belongs.contains_key(bot));
+    let bot = match idle {
+        Some(bot) => bot,
+        None => {
+            drop(hall);
+            let bot = add_bot(&state.db_pool).await?;
+            hall = state.hall.write().await;
+            // the room may have changed meanwhile, the account stays for later
+            check_add_bot(state, &hall, room_id, uid)?;
+            bot
+        }
+    };
+
+    let tx2clients = &state.tx2clients;
+    let (tx, rx) = tx2clients.channel();
+    if !tx2clients.insert(bot, tx) {
+        return Err(AppError::TxAlreadyExist);
+    }
+    tokio::spawn(run_bot(state.clone(), room_id, bot, strategy.build(), rx));
+
+    hall.belongs.insert(bot, room_id);
+    let room = hall.rooms.get_mut(&room_id).unwrap();
+    room.players.push(bot);
+    room.bots.insert(bot);
+    room.strategies.insert(bot, strategy);
+    room.ready.insert(bot);
+    if let Some(seat) = room.seats.iter_mut().find(|seat| seat.is_none()) {
+        *seat = Some(bot);
+    }
+    notify_room(state, room, ServerMessage::MemberJoined(bot)).await;
+    notify_room(state, room, ServerMessage::ReadyState((bot, true))).await;
+    notify_lobby(state, room_id, Some(room)).await;
+
+    if room.can_start() {
+        return start_game(state, room_id, room).await;
+    }
+    return Ok(());
+}
+
+async fn room_private(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    password: Option<String>,
+) -> Result<String, AppError> {
+    let mut hall = state.hall.write().await;
+    if !hall.rooms.contains_key(&room_id) {
+        return Err(AppError::RoomNotExist);
+    } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
+        return Err(AppError::UserNotInRoom);
+    } else if hall.rooms[&room_id].owner != uid {
+        return Err(AppError::NotRoomOwner);
+    } else {
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        notify_lobby(state, room_id, None).await;
+        match password.filter(|password| password.len() != 0) {
+            Some(password) => {
+                room.privacy = Privacy::Password(hash_password(&password));
+                return Ok(String::new());
+            }
+            None => {
+                let code = hex::encode(rand::random::<[u8; 4]>());
+                room.privacy = Privacy::InviteCode(code.clone());
+                return Ok(code);
+            }
         }
     }
 }
 
-async fn room_leave(state: &AppState, room_id: usize, uid: u64) -> Result<(), AppError> {
+async fn room_public(state: &AppState, room_id: usize, uid: u64) -> Result<(), AppError> {
     let mut hall = state.hall.write().await;
     if !hall.rooms.contains_key(&room_id) {
         return Err(AppError::RoomNotExist);
     } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
         return Err(AppError::UserNotInRoom);
+    } else if hall.rooms[&room_id].owner != uid {
+        return Err(AppError::NotRoomOwner);
     } else {
-        hall.belongs.remove(&uid);
         let room = hall.rooms.get_mut(&room_id).unwrap();
-        room.remove(&uid);
-        if room.len() == 0 {
-            hall.rooms.remove(&room_id);
-        }
+        room.privacy = Privacy::Public;
+        notify_lobby(state, room_id, Some(room)).await;
         return Ok(());
     }
 }
 
-async fn room_start(state: &AppState, room_id: usize, uid: u64) -> Result<(), AppError> {
-    let hall = state.hall.read().await;
+async fn room_spectate(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    params: RoomJoinParams,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
+    if let Some(&room_id) = hall.belongs.get(&uid).or(hall.spectating.get(&uid)) {
+        return Err(AppError::UserAlreadyInRoom(room_id));
+    }
+    let room = hall.rooms.get_mut(&room_id).ok_or(AppError::RoomNotExist)?;
+    room.check_access(&params)?;
+
+    let tx2games = &state.tx2games;
+    match tx2games.send(&room_id, GameMessage::Spectate(uid)) {
+        Err(AppError::TxNotExist) => return Err(AppError::GameNotExist),
+        Err(e) => return Err(e),
+        Ok(_) => (),
+    }
+    room.spectators.insert(uid);
+    hall.spectating.insert(uid, room_id);
+    route(state, uid, Some(room_id));
+    state.queue.write().await.remove(uid);
+    return Ok(());
+}
+
+async fn room_rules(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    params: RoomRulesParams,
+) -> Result<(), AppError> {
+    let mut hall = state.hall.write().await;
     if !hall.rooms.contains_key(&room_id) {
         return Err(AppError::RoomNotExist);
     } else if !hall.belongs.contains_key(&uid) || room_id != hall.belongs[&uid] {
         return Err(AppError::UserNotInRoom);
-    } else if hall.rooms[&room_id].len() != 4 {
-        return Err(AppError::RoomNotFull);
+    } else if hall.rooms[&room_id].owner != uid {
+        return Err(AppError::NotRoomOwner);
+    } else if state.tx2games.contains(&room_id) {
+        return Err(AppError::GameAlreadyStart);
+    } else if params
+        .spectator_delay
+        .is_some_and(|i| i > MAX_SPECTATOR_DELAY)
+        || params
+            .turn_time_limit
+            .is_some_and(|i| i > MAX_TURN_TIME_LIMIT)
+    {
+        return Err(AppError::InvalidRules);
     } else {
-        let mut players = Vec::with_capacity(4);
-        for &i in hall.rooms[&room_id].iter() {
-            players.push(i);
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        let rules = &mut room.rules;
+        if let Some(open_hands) = params.spectator_open_hands {
+            rules.spectator_open_hands = open_hands;
         }
+        if let Some(delay) = params.spectator_delay {
+            rules.spectator_delay = delay;
+        }
+        if let Some(spectator_chat) = params.spectator_chat {
+            rules.spectator_chat = spectator_chat;
+        }
+        if let Some(save_chat) = params.save_chat {
+            rules.save_chat = save_chat;
+        }
+        if let Some(limit) = params.turn_time_limit {
+            rules.turn_time_limit = limit;
+        }
+        notify_lobby(state, room_id, Some(room)).await;
+        return Ok(());
+    }
+}
 
-        let (tx, mut rx) = mpsc::unbounded_channel::<(u64, ClientMessage)>();
-
-        let players: [u64; 4] = players.try_into().unwrap();
-        let _state = state.clone();
-        tokio::spawn(async move {
-            let state = _state;
-
-            let mut game = Game::new(players, state.tx2clients);
-            game.game_start().await;
-            while let Some((msg_uid, msg)) = rx.recv().await {
-                if game.handle_message(msg, msg_uid).await {
-                    break;
-                }
-            }
-
-            let game = Arc::new(game);
-            match add_game(&state.db_pool, game.clone()).await {
-                Ok(game_id) => {
-                    game.broadcast(ServerMessage::GameEnd(game_id)).await;
+async fn room_view(state: &AppState, room_id: usize, uid: u64) -> Result<String, AppError> {
+    let mut view;
+    {
+        let hall = state.hall.read().await;
+        let tx2games = &state.tx2games;
+        match hall.rooms.get(&room_id) {
+            Some(room) => {
+                let is_member = room.players.contains(&uid);
+                // private rooms are invisible to outsiders
+                if room.is_private() && !is_member {
+                    return Err(AppError::RoomNotExist);
                 }
-                Err(e) => {
-                    tracing::error!("{:?}", e);
+                view = RoomView::new(room_id, room, tx2games.contains(&room_id));
+                if let Privacy::InviteCode(code) = &room.privacy {
+                    view.invite_code = Some(code.clone());
                 }
             }
+            None => return Err(AppError::RoomNotExist),
+        }
+    }
+    view.fill_usernames(state).await?;
+    return Ok(serde_json::to_string(&view)?);
+}
 
-            let mut tx2games = state.tx2games.write().await;
-            tx2games.delete(&room_id);
-        });
+async fn room_list(state: &AppState, joinable: bool) -> Result<String, AppError> {
+    let mut views = Vec::new();
+    {
+        let hall = state.hall.read().await;
+        let tx2games = &state.tx2games;
+        for (&room_id, room) in hall.rooms.iter() {
+            let playing = tx2games.contains(&room_id);
+            if room.is_private() {
+                continue;
+            }
+            if joinable && (playing || room.players.len() >= 4) {
+                continue;
+            }
+            views.push(RoomView::new(room_id, room, playing));
+        }
+    }
+    views.sort_by_key(|view| view.room_id);
 
-        let mut tx2rooms = state.tx2games.write().await;
-        tx2rooms.insert(room_id, tx);
+    for view in views.iter_mut() {
+        view.fill_usernames(state).await?;
+    }
+    return Ok(serde_json::to_string(&views)?);
+}
 
-        return Ok(());
+// the password and code travel in the body, a request without one has neither
+fn join_params(
+    params: Result<Form<RoomJoinParams>, FormRejection>,
+) -> Result<RoomJoinParams, FormRejection> {
+    match params {
+        Ok(Form(params)) => return Ok(params),
+        Err(FormRejection::InvalidFormContentType(_)) => return Ok(RoomJoinParams::default()),
+        Err(e) => return Err(e),
     }
 }
 
@@ -113,8 +1251,13 @@ pub async fn handle_room_join(
     Path(room_id): Path<usize>,
     State(state): State<AppState>,
     Extension(uid): Extension<u64>,
+    params: Result<Form<RoomJoinParams>, FormRejection>,
 ) -> http::Response<Body> {
-    match room_join(&state, room_id, uid).await {
+    let params = match join_params(params) {
+        Ok(params) => params,
+        Err(e) => return e.into_response(),
+    };
+    match room_join(&state, room_id, uid, params).await {
         Ok(_) => return http::StatusCode::OK.into_response(),
         Err(AppError::UserAlreadyInRoom(room_id)) => {
             return (
@@ -129,6 +1272,12 @@ pub async fn handle_room_join(
         Err(AppError::RoomAlreadyFull) => {
             return (http::StatusCode::CONFLICT, "room is full").into_response();
         }
+        Err(AppError::RoomPasswordIncorrect) => {
+            return (http::StatusCode::FORBIDDEN, "room password incorrect").into_response();
+        }
+        Err(AppError::InviteCodeIncorrect) => {
+            return (http::StatusCode::FORBIDDEN, "invite code incorrect").into_response();
+        }
         Err(e) => {
             tracing::error!("{:?}", e);
             return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
@@ -149,6 +1298,9 @@ pub async fn handle_room_leave(
         Err(AppError::UserNotInRoom) => {
             return (http::StatusCode::CONFLICT, "user not in room").into_response();
         }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
         Err(e) => {
             tracing::error!("{:?}", e);
             return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
@@ -172,6 +1324,312 @@ pub async fn handle_room_start(
         Err(AppError::RoomNotFull) => {
             return (http::StatusCode::CONFLICT, "room not full").into_response();
         }
+        Err(AppError::RoomNotReady) => {
+            return (http::StatusCode::CONFLICT, "room not ready").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(AppError::SeatNotChosen) => {
+            return (http::StatusCode::CONFLICT, "seat not chosen").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+async fn handle_ready_change(
+    state: &AppState,
+    room_id: usize,
+    uid: u64,
+    ready: bool,
+) -> http::Response<Body> {
+    match room_ready(state, room_id, uid, ready).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_ready(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    return handle_ready_change(&state, room_id, uid, true).await;
+}
+
+pub async fn handle_room_unready(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    return handle_ready_change(&state, room_id, uid, false).await;
+}
+
+pub async fn handle_room_list(
+    Query(params): Query<RoomListParams>,
+    State(state): State<AppState>,
+) -> Response {
+    match room_list(&state, params.joinable).await {
+        Ok(res) => return res.into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_view(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> Response {
+    match room_view(&state, room_id, uid).await {
+        Ok(res) => return res.into_response(),
+        Err(AppError::RoomNotExist) => return http::StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_seating(
+    Path((room_id, seating)): Path<(usize, SeatingPolicy)>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match room_seating(&state, room_id, uid, seating).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::NotRoomOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not room owner").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_seat(
+    Path((room_id, seat)): Path<(usize, usize)>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match room_seat(&state, room_id, uid, seat).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::SeatNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "seat not exist").into_response();
+        }
+        Err(AppError::SeatTaken) => {
+            return (http::StatusCode::CONFLICT, "seat taken").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_kick(
+    Path((room_id, target)): Path<(usize, u64)>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match room_kick(&state, room_id, uid, target).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::NotRoomOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not room owner").into_response();
+        }
+        Err(AppError::CannotKickSelf) => {
+            return (http::StatusCode::CONFLICT, "cannot kick yourself").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_bot(
+    Path(room_id): Path<usize>,
+    Query(params): Query<RoomBotParams>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match room_add_bot(&state, room_id, uid, params.strategy).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::NotRoomOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not room owner").into_response();
+        }
+        Err(AppError::RoomAlreadyFull) => {
+            return (http::StatusCode::CONFLICT, "room is full").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_private(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+    Form(params): Form<RoomPrivateParams>,
+) -> http::Response<Body> {
+    match room_private(&state, room_id, uid, params.password).await {
+        Ok(code) => return code.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::NotRoomOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not room owner").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_public(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match room_public(&state, room_id, uid).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::NotRoomOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not room owner").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_spectate(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+    params: Result<Form<RoomJoinParams>, FormRejection>,
+) -> http::Response<Body> {
+    let params = match join_params(params) {
+        Ok(params) => params,
+        Err(e) => return e.into_response(),
+    };
+    match room_spectate(&state, room_id, uid, params).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::UserAlreadyInRoom(room_id)) => {
+            return (
+                http::StatusCode::CONFLICT,
+                format!("user already in room {}", room_id),
+            )
+                .into_response();
+        }
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::GameNotExist) => {
+            return (http::StatusCode::CONFLICT, "game not start").into_response();
+        }
+        Err(AppError::RoomPasswordIncorrect) => {
+            return (http::StatusCode::FORBIDDEN, "room password incorrect").into_response();
+        }
+        Err(AppError::InviteCodeIncorrect) => {
+            return (http::StatusCode::FORBIDDEN, "invite code incorrect").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_room_rules(
+    Path(room_id): Path<usize>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+    Form(params): Form<RoomRulesParams>,
+) -> http::Response<Body> {
+    match room_rules(&state, room_id, uid, params).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::RoomNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "room not exist").into_response();
+        }
+        Err(AppError::UserNotInRoom) => {
+            return (http::StatusCode::CONFLICT, "user not in room").into_response();
+        }
+        Err(AppError::NotRoomOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not room owner").into_response();
+        }
+        Err(AppError::GameAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "game already start").into_response();
+        }
+        Err(AppError::InvalidRules) => {
+            return (http::StatusCode::BAD_REQUEST, "invalid rules").into_response();
+        }
         Err(e) => {
             tracing::error!("{:?}", e);
             return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
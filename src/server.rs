@@ -7,9 +7,15 @@ use deadpool_sqlite::{Config, Runtime};
 use maj_spirit::config::{DATABASE_FILE, LISTEN_ADDR};
 use maj_spirit::state::AppState;
 use maj_spirit::{
-    handle_get_game_detail, handle_get_rankings, handle_get_round_detail, handle_get_username,
-    handle_hello, handle_login, handle_register, handle_room_join, handle_room_leave,
-    handle_room_start, handle_ws, init_db, jwt_auth,
+    handle_get_game_chat, handle_get_game_detail, handle_get_metrics, handle_get_rankings,
+    handle_get_rating, handle_get_round_detail, handle_get_username, handle_hello, handle_login,
+    handle_queue_join, handle_queue_leave, handle_register, handle_room_bot, handle_room_join,
+    handle_room_kick, handle_room_leave, handle_room_list, handle_room_private, handle_room_public,
+    handle_room_ready, handle_room_rules, handle_room_seat, handle_room_seating,
+    handle_room_spectate, handle_room_start, handle_room_unready, handle_room_view,
+    handle_tournament_create, handle_tournament_register, handle_tournament_standings,
+    handle_tournament_start, handle_tournament_view, handle_ws, init_db, jwt_auth, restore_games,
+    run_matchmaking,
 };
 
 #[tokio::main]
@@ -24,19 +30,49 @@ async fn main() {
     init_db(&db_pool).await.unwrap();
 
     let state = AppState::new(db_pool);
+    restore_games(&state).await.unwrap();
+    tokio::spawn(run_matchmaking(state.clone()));
 
     let app = Router::new()
         .route("/hello", get(handle_hello))
+        .route("/room/{id}", get(handle_room_view))
         .route("/room/{id}/join", post(handle_room_join))
         .route("/room/{id}/leave", post(handle_room_leave))
         .route("/room/{id}/start", post(handle_room_start))
+        .route("/room/{id}/ready", post(handle_room_ready))
+        .route("/room/{id}/unready", post(handle_room_unready))
+        .route("/room/{id}/seating/{policy}", post(handle_room_seating))
+        .route("/room/{id}/seat/{n}", post(handle_room_seat))
+        .route("/room/{id}/private", post(handle_room_private))
+        .route("/room/{id}/public", post(handle_room_public))
+        .route("/room/{id}/rules", post(handle_room_rules))
+        .route("/room/{id}/spectate", post(handle_room_spectate))
+        .route("/room/{id}/bot", post(handle_room_bot))
+        .route("/room/{id}/kick/{uid}", post(handle_room_kick))
+        .route("/tournament", post(handle_tournament_create))
+        .route(
+            "/tournament/{id}/register",
+            post(handle_tournament_register),
+        )
+        .route("/tournament/{id}/start", post(handle_tournament_start))
+        .route("/queue/join", post(handle_queue_join))
+        .route("/queue/leave", post(handle_queue_leave))
         .route("/ws", any(handle_ws))
+        .route("/metrics", get(handle_get_metrics))
         .route_layer(middleware::from_fn(jwt_auth))
         .route("/register", post(handle_register))
         .route("/login", post(handle_login))
         .route("/user/{uid}/name", get(handle_get_username))
+        .route("/user/{uid}/rating", get(handle_get_rating))
+        .route("/rooms", get(handle_room_list))
+        .route("/tournament/{id}", get(handle_tournament_view))
+        .route(
+            "/tournament/{id}/standings",
+            get(handle_tournament_standings),
+        )
         .route("/game/{game_id}/rankings", get(handle_get_rankings))
         .route("/game/{game_id}/detail", get(handle_get_game_detail))
+        .route("/game/{game_id}/chat", get(handle_get_game_chat))
         .route(
             "/game/{game_id}/round/{round_id}/detail",
             get(handle_get_round_detail),
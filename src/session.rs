@@ -0,0 +1,224 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, RandomState};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::{DISCONNECT_GRACE, REPLAY_BUFFER_LEN, TX_SHARDS};
+use crate::error::AppError;
+use crate::txmanager::{FullPolicy, Tx, TxManager};
+use crate::ws::ServerMessage;
+
+// the latest messages sent to one user, for resuming after a short disconnect
+#[derive(Default, Debug)]
+struct ReplayBuffer {
+    last_seq: u64,
+    messages: VecDeque<(u64, ServerMessage)>,
+    // when the last connection of the user closed
+    closed_at: Option<Instant>,
+}
+
+impl ReplayBuffer {
+    fn push(&mut self, msg: ServerMessage) -> ServerMessage {
+        self.last_seq += 1;
+        let msg = ServerMessage::Sequenced {
+            seq: self.last_seq,
+            msg: Box::new(msg),
+        };
+        if self.messages.len() == REPLAY_BUFFER_LEN {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((self.last_seq, msg.clone()));
+        return msg;
+    }
+
+    // messages after `last_seq`, or None if some of them were dropped
+    fn since(&self, last_seq: u64) -> Option<Vec<ServerMessage>> {
+        if last_seq > self.last_seq {
+            return None;
+        }
+        let first = self
+            .messages
+            .front()
+            .map_or(self.last_seq + 1, |&(seq, _)| seq);
+        if first > last_seq + 1 {
+            return None;
+        }
+        let missed = self
+            .messages
+            .iter()
+            .filter(|&&(seq, _)| seq > last_seq)
+            .map(|(_, msg)| msg.clone())
+            .collect();
+        return Some(missed);
+    }
+}
+
+/// Connections of the users, every message to a user with a session is
+/// numbered as `ServerMessage::Sequenced` and kept for resuming.
+///
+/// A session starts with the first connection and outlives it by
+/// `DISCONNECT_GRACE`. Bots and users who never connected have none, their
+/// messages are sent as they are. The buffer of a user is locked while a
+/// message is sent, so the numbers follow the order of delivery.
+#[derive(Debug)]
+pub struct Sessions {
+    clients: TxManager<u64, ServerMessage>,
+    buffers: Vec<Mutex<HashMap<u64, ReplayBuffer>>>,
+    hasher: RandomState,
+}
+
+impl Deref for Sessions {
+    type Target = TxManager<u64, ServerMessage>;
+
+    fn deref(&self) -> &Self::Target {
+        return &self.clients;
+    }
+}
+
+impl Sessions {
+    pub fn new(capacity: usize, policy: FullPolicy) -> Self {
+        Self {
+            clients: TxManager::new(capacity, policy),
+            buffers: (0..TX_SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, uid: &u64) -> &Mutex<HashMap<u64, ReplayBuffer>> {
+        let i = self.hasher.hash_one(uid) as usize % self.buffers.len();
+        return &self.buffers[i];
+    }
+
+    pub fn send(&self, uid: &u64, msg: ServerMessage) -> Result<(), AppError> {
+        let mut buffers = self.shard(uid).lock().unwrap();
+        let msg = match buffers.get_mut(uid) {
+            Some(buffer) => buffer.push(msg),
+            None => msg,
+        };
+        return self.clients.send(uid, msg);
+    }
+
+    /// Makes `tx` the connection of `uid` and returns the one it replaces.
+    ///
+    /// The messages after `last_seq` are queued on `tx` before anything else,
+    /// nothing is replayed if some of them are gone.
+    pub fn open(
+        &self,
+        uid: u64,
+        tx: Tx<ServerMessage>,
+        last_seq: Option<u64>,
+    ) -> Option<Tx<ServerMessage>> {
+        let mut buffers = self.shard(&uid).lock().unwrap();
+        let buffer = buffers.entry(uid).or_default();
+        buffer.closed_at = None;
+        let missed = last_seq.and_then(|last_seq| buffer.since(last_seq));
+        for msg in missed.into_iter().flatten() {
+            if let Err(e) = tx.send(msg) {
+                tracing::debug!("{:?}", e);
+            }
+        }
+        return self.clients.replace(uid, tx);
+    }
+
+    /// Sends the messages after `last_seq` again, false if some of them are gone.
+    pub fn resume(&self, uid: u64, last_seq: u64) -> bool {
+        let buffers = self.shard(&uid).lock().unwrap();
+        let Some(missed) = buffers.get(&uid).and_then(|buffer| buffer.since(last_seq)) else {
+            return false;
+        };
+        for msg in missed {
+            if let Err(e) = self.clients.send(&uid, msg) {
+                tracing::debug!("{:?}", e);
+            }
+        }
+        return true;
+    }
+
+    // called once the connection is gone, the session is kept for a while
+    pub fn close(&self, uid: u64) {
+        let mut buffers = self.shard(&uid).lock().unwrap();
+        if self.clients.contains(&uid) {
+            return;
+        }
+        if let Some(buffer) = buffers.get_mut(&uid) {
+            buffer.closed_at = Some(Instant::now());
+        }
+    }
+
+    /// Ends the session of `uid` if they stayed away for `DISCONNECT_GRACE`,
+    /// true if it ended.
+    pub fn expire(&self, uid: u64) -> bool {
+        let mut buffers = self.shard(&uid).lock().unwrap();
+        let grace = Duration::from_secs(DISCONNECT_GRACE);
+        let expired = buffers.get(&uid).is_some_and(|buffer| {
+            buffer
+                .closed_at
+                .is_some_and(|closed_at| closed_at.elapsed() >= grace)
+        });
+        if expired {
+            buffers.remove(&uid);
+        }
+        return expired;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    fn seqs(rx: &mut crate::txmanager::Rx<ServerMessage>) -> Vec<u64> {
+        let mut seqs = Vec::new();
+        while let Some(Some(msg)) = rx.recv().now_or_never() {
+            match msg {
+                ServerMessage::Sequenced { seq, .. } => seqs.push(seq),
+                msg => panic!("not sequenced: {:?}", msg),
+            }
+        }
+        return seqs;
+    }
+
+    #[tokio::test]
+    async fn reconnect_replays_missed_messages() {
+        let sessions = Sessions::new(REPLAY_BUFFER_LEN * 2, FullPolicy::Reject);
+        let (tx, mut rx) = sessions.channel();
+        sessions.open(1, tx.clone(), None);
+        sessions.send(&1, ServerMessage::Paused).unwrap();
+        assert_eq!(seqs(&mut rx), vec![1]);
+
+        sessions.delete_if_same(&1, &tx);
+        sessions.close(1);
+        for _ in 0..3 {
+            assert!(sessions.send(&1, ServerMessage::Resumed).is_err());
+        }
+
+        let (tx, mut rx) = sessions.channel();
+        sessions.open(1, tx, Some(1));
+        sessions.send(&1, ServerMessage::Paused).unwrap();
+        assert_eq!(seqs(&mut rx), vec![2, 3, 4, 5]);
+        assert!(sessions.resume(1, 3));
+        assert_eq!(seqs(&mut rx), vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_replayed_once_messages_are_dropped() {
+        let sessions = Sessions::new(REPLAY_BUFFER_LEN * 2, FullPolicy::Reject);
+        let (tx, _rx) = sessions.channel();
+        sessions.open(1, tx.clone(), None);
+        sessions.delete_if_same(&1, &tx);
+        sessions.close(1);
+        for _ in 0..REPLAY_BUFFER_LEN + 1 {
+            assert!(sessions.send(&1, ServerMessage::Resumed).is_err());
+        }
+
+        let (tx, mut rx) = sessions.channel();
+        sessions.open(1, tx, Some(0));
+        assert_eq!(seqs(&mut rx), Vec::<u64>::new());
+        assert!(!sessions.resume(1, 0));
+        assert!(sessions.resume(1, 1));
+    }
+}
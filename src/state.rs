@@ -1,18 +1,30 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use deadpool_sqlite::Pool;
 
+use crate::config::{CLIENT_QUEUE_CAPACITY, GAME_QUEUE_CAPACITY};
+use crate::game::GameMessage;
+use crate::matchmaking::MatchQueue;
 use crate::room::Hall;
-use crate::txmanager::TxManager;
-use crate::ws::{ClientMessage, ServerMessage};
+use crate::session::Sessions;
+use crate::txmanager::{FullPolicy, TxManager};
+use crate::ws::ChatLimiter;
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub db_pool: Arc<Pool>,
     pub hall: Arc<RwLock<Hall>>,
-    pub tx2clients: Arc<RwLock<TxManager<u64, ServerMessage>>>,
-    pub tx2games: Arc<RwLock<TxManager<usize, (u64, ClientMessage)>>>,
+    pub tx2clients: Arc<Sessions>,
+    pub tx2games: Arc<TxManager<usize, GameMessage>>,
+    // the running game of each user's room, so sockets reach it without the hall
+    pub routes: Arc<TxManager<u64, GameMessage>>,
+    pub queue: Arc<RwLock<MatchQueue>>,
+    // users subscribed to room list changes
+    pub lobby: Arc<RwLock<HashSet<u64>>>,
+    // chat rate limits by user, so reconnecting does not reset them
+    pub chat_limits: Arc<RwLock<HashMap<u64, ChatLimiter>>>,
 }
 
 impl AppState {
@@ -20,8 +32,12 @@ impl AppState {
         return AppState {
             db_pool,
             hall: Arc::new(RwLock::new(Hall::default())),
-            tx2clients: Arc::new(RwLock::new(TxManager::default())),
-            tx2games: Arc::new(RwLock::new(TxManager::default())),
+            tx2clients: Arc::new(Sessions::new(CLIENT_QUEUE_CAPACITY, FullPolicy::Disconnect)),
+            tx2games: Arc::new(TxManager::new(GAME_QUEUE_CAPACITY, FullPolicy::Reject)),
+            routes: Arc::new(TxManager::new(GAME_QUEUE_CAPACITY, FullPolicy::Reject)),
+            queue: Arc::new(RwLock::new(MatchQueue::default())),
+            lobby: Arc::new(RwLock::new(HashSet::new())),
+            chat_limits: Arc::new(RwLock::new(HashMap::new())),
         };
     }
 }
@@ -0,0 +1,497 @@
+use std::pin::Pin;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Extension, Form, Path, State};
+use axum::http;
+use axum::response::IntoResponse;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{TOURNAMENT_PAIR_RETRY, TOURNAMENT_ROOM_BASE};
+use crate::db::{
+    add_tournament, add_tournament_player, add_tournament_tables, delete_tournament_tables,
+    finish_tournament_table, query_standings, query_tournament, query_tournament_players,
+    query_tournament_tables, start_tournament, update_tournament_round,
+};
+use crate::error::AppError;
+use crate::room::{Hall, Room, leave_hall, start_game};
+use crate::state::AppState;
+use crate::ws::ServerMessage;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pairing {
+    // players with close standings share a table
+    #[default]
+    Swiss,
+    Random,
+}
+
+impl Pairing {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Pairing::Swiss => return "swiss",
+            Pairing::Random => return "random",
+        }
+    }
+
+    pub fn from_db(s: &str) -> Pairing {
+        match s {
+            "random" => return Pairing::Random,
+            _ => return Pairing::Swiss,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentStatus {
+    Registering,
+    Running,
+    Finished,
+}
+
+impl TournamentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TournamentStatus::Registering => return "registering",
+            TournamentStatus::Running => return "running",
+            TournamentStatus::Finished => return "finished",
+        }
+    }
+
+    pub fn from_db(s: &str) -> TournamentStatus {
+        match s {
+            "running" => return TournamentStatus::Running,
+            "finished" => return TournamentStatus::Finished,
+            _ => return TournamentStatus::Registering,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tournament {
+    pub tournament_id: u64,
+    pub name: String,
+    pub owner: u64,
+    // number of games every player plays
+    pub hanchan: usize,
+    pub pairing: Pairing,
+    pub status: TournamentStatus,
+    pub current_round: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TournamentTable {
+    pub round: usize,
+    pub room_id: usize,
+    pub players: [u64; 4],
+    pub game_id: Option<usize>,
+    pub finished: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TournamentView {
+    #[serde(flatten)]
+    pub tournament: Tournament,
+    pub players: Vec<u64>,
+    pub tables: Vec<TournamentTable>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Standing {
+    pub uid: u64,
+    pub score: i64,
+    pub games: usize,
+}
+
+#[derive(Deserialize)]
+pub struct TournamentParams {
+    name: String,
+    hanchan: usize,
+    #[serde(default)]
+    pairing: Pairing,
+}
+
+async fn tournament_create(
+    state: &AppState,
+    uid: u64,
+    params: TournamentParams,
+) -> Result<u64, AppError> {
+    if params.hanchan == 0 || params.name.len() == 0 {
+        return Err(AppError::InvalidRules);
+    }
+    return add_tournament(
+        &state.db_pool,
+        &params.name,
+        uid,
+        params.hanchan,
+        params.pairing,
+    )
+    .await;
+}
+
+async fn tournament_register(
+    state: &AppState,
+    tournament_id: u64,
+    uid: u64,
+) -> Result<(), AppError> {
+    let tournament = query_tournament(&state.db_pool, tournament_id).await?;
+    if tournament.status != TournamentStatus::Registering {
+        return Err(AppError::TournamentAlreadyStart);
+    }
+    return add_tournament_player(&state.db_pool, tournament_id, uid).await;
+}
+
+async fn tournament_start(state: &AppState, tournament_id: u64, uid: u64) -> Result<(), AppError> {
+    let tournament = query_tournament(&state.db_pool, tournament_id).await?;
+    let players = query_tournament_players(&state.db_pool, tournament_id).await?;
+    if tournament.owner != uid {
+        return Err(AppError::NotTournamentOwner);
+    } else if tournament.status != TournamentStatus::Registering {
+        return Err(AppError::TournamentAlreadyStart);
+    } else if players.len() == 0 || players.len() % 4 != 0 {
+        return Err(AppError::TournamentPlayerCount);
+    }
+    {
+        let hall = state.hall.read().await;
+        if players.iter().any(|&uid| is_busy(state, &hall, uid)) {
+            return Err(AppError::TournamentPlayerBusy);
+        }
+    }
+    // only one of concurrent starts gets past this
+    if !start_tournament(&state.db_pool, tournament_id).await? {
+        return Err(AppError::TournamentAlreadyStart);
+    }
+    let result = pair_round(state, &tournament, 0).await;
+    if let Err(AppError::TournamentPlayerBusy) = result {
+        // someone sat down in the meantime, open the tournament again
+        update_tournament_round(
+            &state.db_pool,
+            tournament_id,
+            TournamentStatus::Registering,
+            0,
+        )
+        .await?;
+    }
+    return result;
+}
+
+// players of a running casual game are not pulled out of it
+fn is_busy(state: &AppState, hall: &Hall, uid: u64) -> bool {
+    return hall
+        .belongs
+        .get(&uid)
+        .is_some_and(|room_id| state.tx2games.contains(room_id));
+}
+
+fn is_free(state: &AppState, hall: &Hall, room_id: usize) -> bool {
+    return !hall.rooms.contains_key(&room_id) && !state.tx2games.contains(&room_id);
+}
+
+// seats every player at a table for `round` and starts the games
+async fn pair_round(
+    state: &AppState,
+    tournament: &Tournament,
+    round: usize,
+) -> Result<(), AppError> {
+    let tournament_id = tournament.tournament_id;
+    let mut players = query_tournament_players(&state.db_pool, tournament_id).await?;
+    if tournament.pairing == Pairing::Swiss && round > 0 {
+        players = query_standings(&state.db_pool, tournament_id)
+            .await?
+            .into_iter()
+            .map(|standing| standing.uid)
+            .collect();
+    } else {
+        players.shuffle(&mut rand::rng());
+    }
+
+    let tables = {
+        let hall = state.hall.read().await;
+        if players.iter().any(|&uid| is_busy(state, &hall, uid)) {
+            return Err(AppError::TournamentPlayerBusy);
+        }
+        let mut tables = Vec::with_capacity(players.len() / 4);
+        let mut room_id = TOURNAMENT_ROOM_BASE;
+        for group in players.chunks(4) {
+            while !is_free(state, &hall, room_id) {
+                room_id += 1;
+            }
+            tables.push((room_id, group.try_into().unwrap()));
+            room_id += 1;
+        }
+        tables
+    };
+    // a game can only finish at a table that is already recorded
+    add_tournament_tables(&state.db_pool, tournament_id, round, tables.clone()).await?;
+
+    let mut hall = state.hall.write().await;
+    if players.iter().any(|&uid| is_busy(state, &hall, uid))
+        || tables
+            .iter()
+            .any(|&(room_id, _)| !is_free(state, &hall, room_id))
+    {
+        drop(hall);
+        let room_ids = tables.iter().map(|&(room_id, _)| room_id).collect();
+        delete_tournament_tables(&state.db_pool, tournament_id, round, room_ids).await?;
+        return Err(AppError::TournamentPlayerBusy);
+    }
+    // tournament games take priority over the queue and idle casual rooms
+    {
+        let mut queue = state.queue.write().await;
+        for &uid in players.iter() {
+            queue.remove(uid);
+        }
+    }
+    for &uid in players.iter() {
+        leave_hall(state, &mut hall, uid).await;
+    }
+
+    for &(room_id, group) in tables.iter() {
+        let room = Room {
+            owner: group[0],
+            players: group.to_vec(),
+            ready: group.into_iter().collect(),
+            tournament: Some(tournament_id),
+            ..Default::default()
+        };
+        for uid in group {
+            hall.belongs.insert(uid, room_id);
+        }
+        hall.rooms.insert(room_id, room);
+    }
+    tracing::info!(
+        "tournament {} round {} tables {:?}",
+        tournament_id,
+        round,
+        tables
+    );
+
+    for (i, &(room_id, group)) in tables.iter().enumerate() {
+        {
+            let tx2clients = &state.tx2clients;
+            let msg = ServerMessage::TournamentTable {
+                tournament_id,
+                round,
+                room_id,
+            };
+            for uid in group {
+                match tx2clients.send(&uid, msg.clone()) {
+                    Err(AppError::TxNotExist) | Ok(_) => (),
+                    Err(e) => tracing::error!("{:?}", e),
+                }
+            }
+        }
+        let room = hall.rooms.get_mut(&room_id).unwrap();
+        if let Err(e) = start_game(state, room_id, room).await {
+            // the games already running go on, the tables left are taken down
+            let rest = &tables[i..];
+            for &(room_id, group) in rest {
+                hall.rooms.remove(&room_id);
+                for uid in group {
+                    hall.belongs.remove(&uid);
+                }
+            }
+            drop(hall);
+            let room_ids = rest.iter().map(|&(room_id, _)| room_id).collect();
+            delete_tournament_tables(&state.db_pool, tournament_id, round, room_ids).await?;
+            return Err(e);
+        }
+    }
+    drop(hall);
+
+    return update_tournament_round(
+        &state.db_pool,
+        tournament_id,
+        TournamentStatus::Running,
+        round,
+    )
+    .await;
+}
+
+// pairs `round` once no entrant is busy in a casual game any more
+fn spawn_pair_retry(state: AppState, tournament: Tournament, round: usize) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(TOURNAMENT_PAIR_RETRY)).await;
+            match pair_round(&state, &tournament, round).await {
+                Err(AppError::TournamentPlayerBusy) => continue,
+                Err(e) => tracing::error!("{:?}", e),
+                Ok(_) => (),
+            }
+            return;
+        }
+    });
+}
+
+/// Records a finished tournament game, pairing the next round once every table is done.
+///
+/// Boxed because the next round starts games whose tasks call back into this.
+pub(crate) fn game_finished(
+    state: AppState,
+    tournament_id: u64,
+    room_id: usize,
+    game_id: Option<usize>,
+) -> Pin<Box<dyn Future<Output = Result<(), AppError>> + Send>> {
+    return Box::pin(async move {
+        let state = &state;
+        let tournament = query_tournament(&state.db_pool, tournament_id).await?;
+        let round = tournament.current_round;
+        if !finish_tournament_table(&state.db_pool, tournament_id, round, room_id, game_id).await? {
+            return Ok(());
+        }
+
+        if round + 1 < tournament.hanchan {
+            match pair_round(state, &tournament, round + 1).await {
+                Err(AppError::TournamentPlayerBusy) => {
+                    spawn_pair_retry(state.clone(), tournament, round + 1);
+                    return Ok(());
+                }
+                result => return result,
+            }
+        }
+        update_tournament_round(
+            &state.db_pool,
+            tournament_id,
+            TournamentStatus::Finished,
+            round,
+        )
+        .await?;
+        tracing::info!("tournament {} finished", tournament_id);
+
+        let players = query_tournament_players(&state.db_pool, tournament_id).await?;
+        let tx2clients = &state.tx2clients;
+        for uid in players {
+            match tx2clients.send(&uid, ServerMessage::TournamentEnd(tournament_id)) {
+                Err(AppError::TxNotExist) | Ok(_) => (),
+                Err(e) => tracing::error!("{:?}", e),
+            }
+        }
+        return Ok(());
+    });
+}
+
+async fn tournament_view(state: &AppState, tournament_id: u64) -> Result<String, AppError> {
+    let tournament = query_tournament(&state.db_pool, tournament_id).await?;
+    let players = query_tournament_players(&state.db_pool, tournament_id).await?;
+    let tables = query_tournament_tables(&state.db_pool, tournament_id).await?;
+    let view = TournamentView {
+        tournament,
+        players,
+        tables,
+    };
+    return Ok(serde_json::to_string(&view)?);
+}
+
+async fn tournament_standings(state: &AppState, tournament_id: u64) -> Result<String, AppError> {
+    query_tournament(&state.db_pool, tournament_id).await?;
+    let standings = query_standings(&state.db_pool, tournament_id).await?;
+    return Ok(serde_json::to_string(&standings)?);
+}
+
+pub async fn handle_tournament_create(
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+    Form(params): Form<TournamentParams>,
+) -> http::Response<Body> {
+    match tournament_create(&state, uid, params).await {
+        Ok(tournament_id) => return tournament_id.to_string().into_response(),
+        Err(AppError::InvalidRules) => {
+            return (http::StatusCode::BAD_REQUEST, "invalid tournament").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_tournament_register(
+    Path(tournament_id): Path<u64>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match tournament_register(&state, tournament_id, uid).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::TournamentNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "tournament not exist").into_response();
+        }
+        Err(AppError::TournamentAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "tournament already start").into_response();
+        }
+        Err(AppError::TournamentAlreadyRegistered) => {
+            return (http::StatusCode::CONFLICT, "already registered").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_tournament_start(
+    Path(tournament_id): Path<u64>,
+    State(state): State<AppState>,
+    Extension(uid): Extension<u64>,
+) -> http::Response<Body> {
+    match tournament_start(&state, tournament_id, uid).await {
+        Ok(_) => return http::StatusCode::OK.into_response(),
+        Err(AppError::TournamentNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "tournament not exist").into_response();
+        }
+        Err(AppError::NotTournamentOwner) => {
+            return (http::StatusCode::FORBIDDEN, "not tournament owner").into_response();
+        }
+        Err(AppError::TournamentAlreadyStart) => {
+            return (http::StatusCode::CONFLICT, "tournament already start").into_response();
+        }
+        Err(AppError::TournamentPlayerCount) => {
+            return (
+                http::StatusCode::CONFLICT,
+                "player count must be a multiple of 4",
+            )
+                .into_response();
+        }
+        Err(AppError::TournamentPlayerBusy) => {
+            return (http::StatusCode::CONFLICT, "player in a running game").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_tournament_view(
+    Path(tournament_id): Path<u64>,
+    State(state): State<AppState>,
+) -> http::Response<Body> {
+    match tournament_view(&state, tournament_id).await {
+        Ok(res) => return res.into_response(),
+        Err(AppError::TournamentNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "tournament not exist").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
+
+pub async fn handle_tournament_standings(
+    Path(tournament_id): Path<u64>,
+    State(state): State<AppState>,
+) -> http::Response<Body> {
+    match tournament_standings(&state, tournament_id).await {
+        Ok(res) => return res.into_response(),
+        Err(AppError::TournamentNotExist) => {
+            return (http::StatusCode::NOT_FOUND, "tournament not exist").into_response();
+        }
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+}
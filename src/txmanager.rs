@@ -1,48 +1,212 @@
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::{collections::HashMap, fmt::Debug, hash::Hash};
 
-use tokio::sync::mpsc;
+use serde::Serialize;
+use tokio::sync::{Notify, mpsc};
 
+use crate::config::TX_SHARDS;
 use crate::error::AppError;
 
+// what to do with a message when the receiver is `capacity` messages behind
+#[derive(Debug, Clone, Copy)]
+pub enum FullPolicy {
+    // end the receiver, a reconnecting client gets a snapshot instead of the backlog
+    Disconnect,
+    // refuse the message and keep the receiver
+    Reject,
+}
+
 #[derive(Debug)]
-pub struct TxManager<T: Eq + Hash, M: Debug> {
-    conn: HashMap<T, mpsc::UnboundedSender<M>>,
+pub struct Tx<M> {
+    tx: mpsc::Sender<M>,
+    overflow: Arc<Notify>,
+    policy: FullPolicy,
+    overflows: Arc<AtomicU64>,
 }
 
-impl<T: Eq + Hash, M: Debug> Default for TxManager<T, M> {
-    fn default() -> Self {
+impl<M> Clone for Tx<M> {
+    fn clone(&self) -> Self {
         Self {
-            conn: HashMap::default(),
+            tx: self.tx.clone(),
+            overflow: self.overflow.clone(),
+            policy: self.policy,
+            overflows: self.overflows.clone(),
+        }
+    }
+}
+
+impl<M> Tx<M> {
+    pub fn send(&self, msg: M) -> Result<(), AppError> {
+        match self.tx.try_send(msg) {
+            Ok(_) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+                if let FullPolicy::Disconnect = self.policy {
+                    self.overflow.notify_one();
+                }
+                return Err(AppError::TxFull);
+            }
+            Err(e) => return Err(AppError::MpscSend(e.to_string())),
+        }
+    }
+
+    pub fn same_channel(&self, other: &Tx<M>) -> bool {
+        return self.tx.same_channel(&other.tx);
+    }
+
+    fn queued(&self) -> usize {
+        return self.tx.max_capacity() - self.tx.capacity();
+    }
+}
+
+// a bounded channel with its own overflow count, for queues outside a manager
+pub fn channel<M>(capacity: usize, policy: FullPolicy) -> (Tx<M>, Rx<M>) {
+    return new_channel(capacity, policy, Arc::new(AtomicU64::new(0)));
+}
+
+fn new_channel<M>(
+    capacity: usize,
+    policy: FullPolicy,
+    overflows: Arc<AtomicU64>,
+) -> (Tx<M>, Rx<M>) {
+    let (tx, rx) = mpsc::channel(capacity);
+    let overflow = Arc::new(Notify::new());
+    let tx = Tx {
+        tx,
+        overflow: overflow.clone(),
+        policy,
+        overflows,
+    };
+    return (tx, Rx { rx, overflow });
+}
+
+pub struct Rx<M> {
+    rx: mpsc::Receiver<M>,
+    overflow: Arc<Notify>,
+}
+
+impl<M> Rx<M> {
+    // returns None once the queue has overflowed under `FullPolicy::Disconnect`
+    pub async fn recv(&mut self) -> Option<M> {
+        tokio::select! {
+            biased;
+            _ = self.overflow.notified() => return None,
+            msg = self.rx.recv() => return msg,
         }
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct QueueMetrics {
+    pub channels: usize,
+    pub capacity: usize,
+    pub queued: usize,
+    pub max_queued: usize,
+    pub overflows: u64,
+}
+
+// senders are spread over shards with their own short locks, so rooms and
+// connections hashing to different shards never wait on each other
+#[derive(Debug)]
+pub struct TxManager<T: Eq + Hash, M: Debug> {
+    shards: Vec<RwLock<HashMap<T, Tx<M>>>>,
+    hasher: RandomState,
+    capacity: usize,
+    policy: FullPolicy,
+    overflows: Arc<AtomicU64>,
+}
+
 impl<T: Eq + Hash, M: Debug> TxManager<T, M> {
-    pub fn insert(&mut self, uid: T, tx: mpsc::UnboundedSender<M>) -> bool {
-        if self.conn.contains_key(&uid) {
+    pub fn new(capacity: usize, policy: FullPolicy) -> Self {
+        Self {
+            shards: (0..TX_SHARDS).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            capacity,
+            policy,
+            overflows: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn shard(&self, uid: &T) -> &RwLock<HashMap<T, Tx<M>>> {
+        let i = self.hasher.hash_one(uid) as usize % self.shards.len();
+        return &self.shards[i];
+    }
+
+    // a bounded channel that follows the policy of this manager
+    pub fn channel(&self) -> (Tx<M>, Rx<M>) {
+        return new_channel(self.capacity, self.policy, self.overflows.clone());
+    }
+
+    pub fn insert(&self, uid: T, tx: Tx<M>) -> bool {
+        let mut shard = self.shard(&uid).write().unwrap();
+        if shard.contains_key(&uid) {
             return false;
         } else {
-            self.conn.insert(uid, tx);
+            shard.insert(uid, tx);
             return true;
         }
     }
 
-    pub fn delete(&mut self, uid: &T) -> bool {
-        if !self.conn.contains_key(uid) {
+    // returns the sender that was replaced, if any
+    pub fn replace(&self, uid: T, tx: Tx<M>) -> Option<Tx<M>> {
+        return self.shard(&uid).write().unwrap().insert(uid, tx);
+    }
+
+    // only deletes if `uid` is still bound to `tx`
+    pub fn delete_if_same(&self, uid: &T, tx: &Tx<M>) -> bool {
+        return self.delete_if_same_then(uid, tx, || ());
+    }
+
+    // like `delete_if_same`, then runs `f` before
+    // anyone can bind `uid` again
+    pub fn delete_if_same_then(&self, uid: &T, tx: &Tx<M>, f: impl FnOnce()) -> bool {
+        let mut shard = self.shard(uid).write().unwrap();
+        if !shard.get(uid).is_some_and(|i| i.same_channel(tx)) {
             return false;
         } else {
-            self.conn.remove(uid);
+            shard.remove(uid);
+            f();
             return true;
         }
     }
 
+    pub fn delete(&self, uid: &T) -> bool {
+        return self.shard(uid).write().unwrap().remove(uid).is_some();
+    }
+
+    pub fn contains(&self, uid: &T) -> bool {
+        return self.shard(uid).read().unwrap().contains_key(uid);
+    }
+
+    pub fn get(&self, uid: &T) -> Option<Tx<M>> {
+        return self.shard(uid).read().unwrap().get(uid).cloned();
+    }
+
     pub fn send(&self, uid: &T, msg: M) -> Result<(), AppError> {
-        if !self.conn.contains_key(uid) {
-            return Err(AppError::TxNotExist);
+        match self.shard(uid).read().unwrap().get(uid) {
+            Some(tx) => return tx.send(msg),
+            None => return Err(AppError::TxNotExist),
         }
-        match self.conn[uid].send(msg) {
-            Ok(_) => return Ok(()),
-            Err(e) => return Err(AppError::MpscSend(e.to_string())),
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        let mut metrics = QueueMetrics {
+            channels: 0,
+            capacity: self.capacity,
+            queued: 0,
+            max_queued: 0,
+            overflows: self.overflows.load(Ordering::Relaxed),
+        };
+        for shard in self.shards.iter() {
+            for tx in shard.read().unwrap().values() {
+                let queued = tx.queued();
+                metrics.channels += 1;
+                metrics.queued += queued;
+                metrics.max_queued = metrics.max_queued.max(queued);
+            }
         }
+        return metrics;
     }
 }
@@ -1,16 +1,89 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
 use axum::body::Body;
 use axum::extract::ws;
 use axum::extract::{Extension, State};
 use axum::http::Response;
 use futures_util::SinkExt;
-use futures_util::stream::StreamExt;
-use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use futures_util::stream::{SplitSink, SplitStream, StreamExt};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::RwLock;
+use tokio::time::{Duration, Instant};
 
+use crate::config::{
+    CHAT_RATE_LIMIT, CHAT_RATE_WINDOW, DISCONNECT_GRACE, HANDSHAKE_TIMEOUT, HEARTBEAT_INTERVAL,
+    MIN_PROTOCOL_VERSION, PONG_TIMEOUT, PROTOCOL_VERSION,
+};
 use crate::error::AppError;
-use crate::game::Cards;
+use crate::game::{Cards, GameMessage};
+use crate::room::{
+    GameResult, LobbyRoom, lobby_subscribe, lobby_unsubscribe, room_chat, room_rematch_vote,
+};
 use crate::state::AppState;
 
+// how messages after the handshake are framed, the handshake itself is always JSON
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn encode<T: Serialize>(&self, msg: &T) -> Result<Vec<u8>, AppError> {
+        match self {
+            Encoding::Json => return Ok(serde_json::to_vec(msg)?),
+            Encoding::Msgpack => match rmp_serde::to_vec_named(msg) {
+                Ok(bytes) => return Ok(bytes),
+                Err(e) => return Err(AppError::Encoding(e.to_string())),
+            },
+            Encoding::Cbor => {
+                let mut bytes = Vec::new();
+                match ciborium::into_writer(msg, &mut bytes) {
+                    Ok(_) => return Ok(bytes),
+                    Err(e) => return Err(AppError::Encoding(e.to_string())),
+                }
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, AppError> {
+        match self {
+            Encoding::Json => return Ok(serde_json::from_slice(bytes)?),
+            Encoding::Msgpack => match rmp_serde::from_slice(bytes) {
+                Ok(msg) => return Ok(msg),
+                Err(e) => return Err(AppError::Encoding(e.to_string())),
+            },
+            Encoding::Cbor => match ciborium::from_reader(bytes) {
+                Ok(msg) => return Ok(msg),
+                Err(e) => return Err(AppError::Encoding(e.to_string())),
+            },
+        }
+    }
+
+    // None for a name this server does not know
+    fn from_name(name: &str) -> Option<Encoding> {
+        let name: serde::de::value::StrDeserializer<serde::de::value::Error> =
+            name.into_deserializer();
+        return Encoding::deserialize(name).ok();
+    }
+
+    // JSON goes out as text frames, the binary encodings as binary frames
+    fn frame(&self, msg: &ServerMessage) -> Result<ws::Message, AppError> {
+        match self {
+            Encoding::Json => {
+                let msg = serde_json::to_string(msg)?;
+                return Ok(ws::Message::Text(msg.into()));
+            }
+            _ => return Ok(ws::Message::Binary(self.encode(msg)?.into())),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct GameInfo {
     pub round_id: usize,
@@ -18,19 +91,103 @@ pub struct GameInfo {
     pub players_score: [i64; 4],
 }
 
+// an action the receiver is expected to take
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 #[serde(tag = "tag", content = "content")]
-pub enum ServerMessage {
+pub enum Prompt {
+    Discard,
+    // true when voting to pause, false when voting to resume
+    PauseVote(bool),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TableSnapshot {
+    pub game_info: GameInfo,
+    pub current_player: u64,
+    // discards by seat
+    pub rivers: [Vec<u8>; 4],
+    pub wall_remaining: usize,
+    pub paused: bool,
+    pub disconnected: Vec<u64>,
+    pub prompts: Vec<Prompt>,
+    // none for spectators
+    pub hand: Option<Cards>,
+}
+
+// why a request was rejected
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
     GameNotStart,
     UserNotInRoom,
+    NotPlayer,
     NotCurrentPlayer,
+    NotHaveCard,
+    GamePaused,
+    GameNotPaused,
+    ChatTooLong,
+    ChatRateLimited,
+    RematchNotOpen,
+    // the game is too far behind to take the message
+    ServerBusy,
+    InternalError,
+}
+
+impl ErrorCode {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => return "malformed request",
+            ErrorCode::GameNotStart => return "game not started",
+            ErrorCode::UserNotInRoom => return "user not in room",
+            ErrorCode::NotPlayer => return "not a player",
+            ErrorCode::NotCurrentPlayer => return "not the current player",
+            ErrorCode::NotHaveCard => return "card not in hand",
+            ErrorCode::GamePaused => return "game paused",
+            ErrorCode::GameNotPaused => return "game not paused",
+            ErrorCode::ChatTooLong => return "chat message too long",
+            ErrorCode::ChatRateLimited => return "too many chat messages",
+            ErrorCode::RematchNotOpen => return "no rematch vote open",
+            ErrorCode::ServerBusy => return "server busy",
+            ErrorCode::InternalError => return "internal error",
+        }
+    }
+}
+
+impl From<&AppError> for ErrorCode {
+    fn from(e: &AppError) -> ErrorCode {
+        match e {
+            AppError::Json(_) | AppError::Encoding(_) => return ErrorCode::BadRequest,
+            AppError::TxNotExist => return ErrorCode::GameNotStart,
+            AppError::TxFull => return ErrorCode::ServerBusy,
+            AppError::UserNotInRoom => return ErrorCode::UserNotInRoom,
+            AppError::ChatTooLong => return ErrorCode::ChatTooLong,
+            AppError::ChatRateLimited => return ErrorCode::ChatRateLimited,
+            AppError::RematchNotOpen => return ErrorCode::RematchNotOpen,
+            AppError::ChatNotAllowed => return ErrorCode::NotPlayer,
+            _ => return ErrorCode::InternalError,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "tag", content = "content")]
+pub enum ServerMessage {
+    // answers a request that carried an id and was accepted
+    Ack(u64),
+    // answers every rejected request, `request_id` is None if it carried none
+    Error {
+        request_id: Option<u64>,
+        code: ErrorCode,
+        message: String,
+    },
 
     GameInfoSync(GameInfo),
     CardSync(Cards),
+    TableSnapshot(TableSnapshot),
 
     GetCard(u8),
     Discard((u64, u8)),
-    NotHaveCard,
 
     RoundStart(usize),
     WinAll(u64),
@@ -38,89 +195,473 @@ pub enum ServerMessage {
     Tie,
 
     GameEnd(usize),
+
+    ReadyState((u64, bool)),
+
+    HandsReveal([Cards; 4]),
+
+    MatchFound(usize),
+
+    // milliseconds since the unix epoch
+    Chat {
+        uid: u64,
+        text: String,
+        ts: u64,
+    },
+
+    GameResult(GameResult),
+    RematchVote((u64, bool)),
+
+    MemberJoined(u64),
+    MemberLeft(u64),
+    MemberKicked(u64),
+    RoomClosed(usize),
+    LobbyUpdate(LobbyRoom),
+    LobbyRemove(usize),
+
+    TournamentTable {
+        tournament_id: u64,
+        round: usize,
+        room_id: usize,
+    },
+    TournamentEnd(u64),
+
+    // true for a pause vote, false for a resume vote
+    PauseVote((u64, bool)),
+    Paused,
+    Resumed,
+
+    PlayerDisconnected(u64),
+    PlayerReconnected(u64),
+
+    // sent to the old connection when the same user connects again
+    SessionReplaced,
+
+    // answers to `ClientMessage::Hello`, the connection is closed after `IncompatibleVersion`
+    Welcome {
+        version: u32,
+        capabilities: Vec<String>,
+        encoding: Encoding,
+    },
+    IncompatibleVersion {
+        version: u32,
+        min_version: u32,
+    },
+
+    // every message to a connected user, numbered so it can be resumed. Only
+    // the handshake and `SessionReplaced` to the old connection are not
+    Sequenced {
+        seq: u64,
+        msg: Box<ServerMessage>,
+    },
+}
+
+impl ServerMessage {
+    pub fn error(request_id: Option<u64>, code: ErrorCode) -> ServerMessage {
+        return ServerMessage::Error {
+            request_id,
+            code,
+            message: code.message().to_string(),
+        };
+    }
+}
+
+// a client message with an optional id, echoed in the `Ack` or `Error` it gets back
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub msg: ClientMessage,
+}
+
+impl From<ClientMessage> for ClientRequest {
+    fn from(msg: ClientMessage) -> ClientRequest {
+        return ClientRequest { id: None, msg };
+    }
+}
+
+// what is left of a request that failed to parse
+#[derive(Deserialize)]
+struct RequestId {
+    #[serde(default)]
+    id: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "tag", content = "content")]
 pub enum ClientMessage {
+    // must be the first message on a connection
+    Hello {
+        version: u32,
+        capabilities: Vec<String>,
+        // in order of preference, names this server does not know are skipped
+        // and JSON is used if none is left
+        #[serde(default, deserialize_with = "known_encodings")]
+        encodings: Vec<Encoding>,
+        // the last `Sequenced` message of an earlier connection, what followed
+        // it is sent before anything else
+        #[serde(default)]
+        last_seq: Option<u64>,
+    },
     RequestGameSync,
     RequestCardSync,
     Discard(u8),
+    RequestPause,
+    RequestResume,
+    Chat(String),
+    RematchVote(bool),
+    SubscribeLobby,
+    UnsubscribeLobby,
+    // replays the `Sequenced` messages after `last_seq`, or the game sends a
+    // snapshot if they are gone
+    Resume {
+        last_seq: u64,
+    },
+}
+
+fn known_encodings<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Encoding>, D::Error> {
+    let names = Vec::<String>::deserialize(d)?;
+    return Ok(names
+        .iter()
+        .filter_map(|i| Encoding::from_name(i))
+        .collect());
+}
+
+/// Optional features a client may rely on when the server lists them in `Welcome`.
+///
+/// Compatibility policy: the server speaks `PROTOCOL_VERSION` and accepts every
+/// client from `MIN_PROTOCOL_VERSION` on. Adding a message, a field with a
+/// default or a capability keeps the version. Renaming, removing or changing
+/// the shape of a message bumps `PROTOCOL_VERSION`, and `MIN_PROTOCOL_VERSION`
+/// is only raised once the older shape is no longer served.
+///
+/// With `sequenced` and `resume` a client reconnecting within `DISCONNECT_GRACE`
+/// gets what it missed by sending `last_seq` in `Hello`, see `Sessions`.
+pub const CAPABILITIES: &[&str] = &[
+    "sequenced",
+    "resume",
+    "chat",
+    "lobby",
+    "pause",
+    "msgpack",
+    "cbor",
+];
+
+async fn send_now(
+    ws_tx: &mut SplitSink<ws::WebSocket, ws::Message>,
+    msg: &ServerMessage,
+) -> Result<(), AppError> {
+    let msg = serde_json::to_string(msg)?;
+    if let Err(e) = ws_tx.send(ws::Message::Text(msg.into())).await {
+        return Err(AppError::WebSocket(e.to_string()));
+    }
+    return Ok(());
+}
+
+// waits for `Hello` and answers it, returns the encoding picked for the
+// connection and where the client wants to resume
+async fn handshake(
+    ws_tx: &mut SplitSink<ws::WebSocket, ws::Message>,
+    ws_rx: &mut SplitStream<ws::WebSocket>,
+) -> Result<(Encoding, Option<u64>), AppError> {
+    let first = tokio::time::timeout(Duration::from_secs(HANDSHAKE_TIMEOUT), async {
+        while let Some(msg) = ws_rx.next().await {
+            match msg {
+                Ok(ws::Message::Text(json_text)) => return Some(json_text),
+                Ok(ws::Message::Close(_)) | Err(_) => return None,
+                _ => (),
+            }
+        }
+        return None;
+    })
+    .await;
+    let Ok(Some(json_text)) = first else {
+        return Err(AppError::HandshakeFailed);
+    };
+
+    // anything but a hello comes from a client older than the handshake
+    let (version, capabilities, encodings, last_seq) = match serde_json::from_str(&json_text) {
+        Ok(ClientMessage::Hello {
+            version,
+            capabilities,
+            encodings,
+            last_seq,
+        }) => (version, capabilities, encodings, last_seq),
+        _ => (0, Vec::new(), Vec::new(), None),
+    };
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+        let msg = ServerMessage::IncompatibleVersion {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+        };
+        send_now(ws_tx, &msg).await?;
+        if let Err(e) = ws_tx.send(ws::Message::Close(None)).await {
+            tracing::debug!("{:?}", e);
+        }
+        return Err(AppError::IncompatibleVersion(version));
+    }
+
+    tracing::debug!("client capabilities {:?}", capabilities);
+
+    // every encoding is supported, so the first preference wins
+    let encoding = encodings.first().copied().unwrap_or_default();
+    let msg = ServerMessage::Welcome {
+        version: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES.iter().map(|i| i.to_string()).collect(),
+        encoding,
+    };
+    send_now(ws_tx, &msg).await?;
+    return Ok((encoding, last_seq));
+}
+
+// sliding window over the chat messages sent by one user, across connections
+#[derive(Default, Debug)]
+pub struct ChatLimiter {
+    sent: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    fn expire(&mut self, now: Instant) {
+        let window = Duration::from_secs(CHAT_RATE_WINDOW);
+        while self.sent.front().is_some_and(|&t| now - t >= window) {
+            self.sent.pop_front();
+        }
+    }
+
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        self.expire(now);
+        if self.sent.len() >= CHAT_RATE_LIMIT {
+            return false;
+        }
+        self.sent.push_back(now);
+        return true;
+    }
+
+    // nothing left in the window, forgetting the user changes nothing
+    fn idle(&mut self) -> bool {
+        self.expire(Instant::now());
+        return self.sent.len() == 0;
+    }
 }
 
 async fn handle_socket(socket: ws::WebSocket, state: AppState, uid: u64) {
     let (mut ws_tx, mut ws_rx) = socket.split();
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<ServerMessage>();
+    let (encoding, last_seq) = match handshake(&mut ws_tx, &mut ws_rx).await {
+        Ok(handshake) => handshake,
+        Err(e) => {
+            tracing::info!("handshake with {} failed: {:?}", uid, e);
+            return;
+        }
+    };
+    tracing::debug!("{} connected using {:?}", uid, encoding);
 
-    let mut tx2clients = state.tx2clients.write().await;
-    if !tx2clients.insert(uid, tx.clone()) {
-        match ws_tx.send(ws::Message::Close(None)).await {
-            Err(e) => tracing::error!("{:?}", e),
-            Ok(()) => (),
+    // the newest connection takes over an existing session
+    let tx2clients = &state.tx2clients;
+    let (tx, mut rx) = tx2clients.channel();
+    if let Some(old_tx) = tx2clients.open(uid, tx.clone(), last_seq) {
+        tracing::info!("session of {} replaced", uid);
+        if let Err(e) = old_tx.send(ServerMessage::SessionReplaced) {
+            tracing::debug!("{:?}", e);
         }
-        return;
     }
-    let send_handle = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
+    // when anything was last heard from the client, pongs included
+    let last_seen = Arc::new(RwLock::new(Instant::now()));
+    let _last_seen = last_seen.clone();
+    let mut send_handle = tokio::spawn(async move {
+        let last_seen = _last_seen;
+        let mut heartbeat = tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL));
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                _ = heartbeat.tick() => {
+                    if last_seen.read().await.elapsed() > Duration::from_secs(PONG_TIMEOUT) {
+                        tracing::info!("connection of {} timed out", uid);
+                        if let Err(e) = ws_tx.send(ws::Message::Close(None)).await {
+                            tracing::debug!("{:?}", e);
+                        }
+                        break;
+                    }
+                    if ws_tx.send(ws::Message::Ping(Default::default())).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let Some(msg) = msg else {
+                break;
+            };
             tracing::debug!("send {:?} to {}", msg, uid);
 
-            let msg = serde_json::to_string(&msg).unwrap();
-            if ws_tx.send(ws::Message::Text(msg.into())).await.is_err() {
+            let replaced = matches!(msg, ServerMessage::SessionReplaced);
+            let frame = match encoding.frame(&msg) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    continue;
+                }
+            };
+            if ws_tx.send(frame).await.is_err() {
+                break;
+            }
+            if replaced {
+                if let Err(e) = ws_tx.send(ws::Message::Close(None)).await {
+                    tracing::debug!("{:?}", e);
+                }
                 break;
             }
         }
     });
-    drop(tx2clients);
-
-    let recv_handle = tokio::spawn(async move {
-        let handle_message = async |json_text: &str| -> Result<(), AppError> {
-            let msg = serde_json::from_str(json_text)?;
-            let hall = state.hall.read().await;
-            let tx2games = state.tx2games.read().await;
-            if let Some(room_id) = hall.belongs.get(&uid) {
-                return tx2games.send(room_id, (uid, msg));
-            } else {
-                return Err(AppError::UserNotInRoom);
+
+    // bring a (re)connected member of a running game up to date
+    match state.routes.send(&uid, GameMessage::Connected(uid)) {
+        Err(AppError::TxNotExist) | Ok(_) => (),
+        Err(e) => tracing::error!("{:?}", e),
+    }
+
+    let own_tx = tx;
+    let _state = state.clone();
+    let mut recv_handle = tokio::spawn(async move {
+        let state = _state;
+        // true if the request is answered here, false if it went to the game
+        let handle_message = async |req: ClientRequest| -> Result<bool, AppError> {
+            let ClientRequest { id, msg } = req;
+            if let ClientMessage::Chat(text) = msg {
+                let allowed = state
+                    .chat_limits
+                    .write()
+                    .await
+                    .entry(uid)
+                    .or_default()
+                    .allow();
+                if !allowed {
+                    return Err(AppError::ChatRateLimited);
+                }
+                room_chat(&state, uid, text).await?;
+                return Ok(true);
+            }
+            if let ClientMessage::RematchVote(agree) = msg {
+                room_rematch_vote(&state, uid, agree).await?;
+                return Ok(true);
+            }
+            if let ClientMessage::SubscribeLobby = msg {
+                lobby_subscribe(&state, uid).await?;
+                return Ok(true);
+            }
+            if let ClientMessage::Hello { .. } = msg {
+                return Ok(true);
+            }
+            // the game sends a snapshot if the session cannot replay
+            if let ClientMessage::Resume { last_seq } = msg
+                && state.tx2clients.resume(uid, last_seq)
+            {
+                return Ok(true);
+            }
+            if let ClientMessage::UnsubscribeLobby = msg {
+                lobby_unsubscribe(&state, uid).await;
+                return Ok(true);
+            }
+            let req = ClientRequest { id, msg };
+            match state.routes.send(&uid, GameMessage::Client((uid, req))) {
+                Ok(_) => return Ok(false),
+                // no game, or it just ended: the hall knows why
+                Err(AppError::TxNotExist) | Err(AppError::MpscSend(_)) => {
+                    let hall = state.hall.read().await;
+                    if hall.belongs.contains_key(&uid) || hall.spectating.contains_key(&uid) {
+                        return Err(AppError::TxNotExist);
+                    } else {
+                        return Err(AppError::UserNotInRoom);
+                    }
+                }
+                Err(e) => return Err(e),
             }
         };
 
         while let Some(msg) = ws_rx.next().await {
-            match msg {
+            *last_seen.write().await = Instant::now();
+            // text frames are JSON whatever was negotiated
+            let (frame_encoding, bytes): (Encoding, &[u8]) = match &msg {
                 Ok(ws::Message::Text(json_text)) => {
                     tracing::debug!("recv {:?} from {}", json_text, uid);
+                    (Encoding::Json, json_text.as_bytes())
+                }
+                Ok(ws::Message::Binary(bytes)) => {
+                    tracing::debug!("recv {} bytes from {}", bytes.len(), uid);
+                    (encoding, bytes)
+                }
+                Ok(ws::Message::Close(_)) => break,
+                Err(e) => {
+                    tracing::error!("{:?}", e);
+                    break;
+                }
+                _ => continue,
+            };
 
-                    match handle_message(&json_text).await {
-                        Err(AppError::TxNotExist) => {
-                            tx.send(ServerMessage::GameNotStart).unwrap();
-                        }
-                        Err(AppError::UserNotInRoom) => {
-                            tx.send(ServerMessage::UserNotInRoom).unwrap();
-                        }
+            let reply = match frame_encoding.decode::<ClientRequest>(bytes) {
+                Ok(req) => {
+                    let id = req.id;
+                    match handle_message(req).await {
+                        Ok(true) => match id {
+                            Some(id) => ServerMessage::Ack(id),
+                            None => continue,
+                        },
+                        Ok(false) => continue,
                         Err(e) => {
-                            tracing::error!("{:?}", e);
+                            let code = ErrorCode::from(&e);
+                            if code == ErrorCode::InternalError {
+                                tracing::error!("{:?}", e);
+                            }
+                            ServerMessage::error(id, code)
                         }
-                        _ => (),
                     }
                 }
-                Ok(ws::Message::Close(_)) => break,
                 Err(e) => {
-                    tracing::error!("{:?}", e);
-                    break;
+                    tracing::debug!("{:?}", e);
+                    let id = frame_encoding.decode::<RequestId>(bytes).ok();
+                    ServerMessage::error(id.and_then(|i| i.id), ErrorCode::BadRequest)
                 }
-                _ => (),
+            };
+            if let Err(e) = state.tx2clients.send(&uid, reply) {
+                tracing::debug!("{:?}", e);
             }
         }
     });
 
+    // a half-open connection never ends the recv task on its own
     tokio::select! {
-        _ = recv_handle => {},
-        _ = send_handle => {},
+        _ = &mut recv_handle => send_handle.abort(),
+        _ = &mut send_handle => recv_handle.abort(),
     }
 
-    let mut tx2clients = state.tx2clients.write().await;
-    if !tx2clients.delete(&uid) {
-        tracing::error!("this should not happen");
+    // a replaced session leaves everything to the one that took over,
+    // the shard stays locked so a reconnect is seen by the game after this
+    let deleted = state.tx2clients.delete_if_same_then(&uid, &own_tx, || {
+        match state.routes.send(&uid, GameMessage::Disconnected(uid)) {
+            Err(AppError::TxNotExist) | Ok(_) => (),
+            Err(e) => tracing::error!("{:?}", e),
+        }
+    });
+    if !deleted {
+        return;
+    }
+    state.tx2clients.close(uid);
+    // a match found now would start without them
+    state.queue.write().await.remove(uid);
+    let mut chat_limits = state.chat_limits.write().await;
+    if chat_limits
+        .get_mut(&uid)
+        .is_some_and(|limiter| limiter.idle())
+    {
+        chat_limits.remove(&uid);
+    }
+    drop(chat_limits);
+
+    // lobby updates keep coming for a short disconnect, to be resumed
+    tokio::time::sleep(Duration::from_secs(DISCONNECT_GRACE)).await;
+    if state.tx2clients.expire(uid) {
+        lobby_unsubscribe(&state, uid).await;
     }
 }
 
@@ -131,3 +672,136 @@ pub async fn handle_ws(
 ) -> Response<Body> {
     return ws.on_upgrade(move |socket| handle_socket(socket, state, uid));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the messages have no `PartialEq`, their JSON form is compared instead
+    fn round_trip<T: Serialize + DeserializeOwned>(encoding: Encoding, msg: &T) {
+        let bytes = encoding.encode(msg).unwrap();
+        let decoded: T = encoding.decode(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(msg).unwrap()
+        );
+    }
+
+    fn client_requests() -> Vec<ClientRequest> {
+        return vec![
+            ClientRequest {
+                id: Some(7),
+                msg: ClientMessage::Discard(33),
+            },
+            ClientMessage::RequestCardSync.into(),
+            ClientRequest {
+                id: Some(u64::MAX),
+                msg: ClientMessage::Chat("你好".to_string()),
+            },
+            ClientMessage::Resume { last_seq: 42 }.into(),
+            ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+                capabilities: vec!["sequenced".to_string()],
+                encodings: vec![Encoding::Cbor, Encoding::Msgpack],
+                last_seq: Some(7),
+            }
+            .into(),
+        ];
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        let mut cards = Cards::default();
+        for card in [0, 0, 9, 18, 27, 33] {
+            cards.insert(card);
+        }
+        return vec![
+            ServerMessage::CardSync(cards),
+            ServerMessage::HandsReveal([cards, Cards::default(), cards, cards]),
+            ServerMessage::Sequenced {
+                seq: 12,
+                msg: Box::new(ServerMessage::CardSync(cards)),
+            },
+            ServerMessage::Sequenced {
+                seq: 13,
+                msg: Box::new(ServerMessage::Discard((5, 27))),
+            },
+            ServerMessage::error(Some(3), ErrorCode::NotHaveCard),
+            ServerMessage::error(None, ErrorCode::BadRequest),
+            ServerMessage::Ack(3),
+            ServerMessage::Welcome {
+                version: PROTOCOL_VERSION,
+                capabilities: CAPABILITIES.iter().map(|i| i.to_string()).collect(),
+                encoding: Encoding::Msgpack,
+            },
+        ];
+    }
+
+    #[test]
+    fn msgpack_round_trip() {
+        for req in client_requests() {
+            round_trip(Encoding::Msgpack, &req);
+        }
+        for msg in server_messages() {
+            round_trip(Encoding::Msgpack, &msg);
+        }
+    }
+
+    #[test]
+    fn cbor_round_trip() {
+        for req in client_requests() {
+            round_trip(Encoding::Cbor, &req);
+        }
+        for msg in server_messages() {
+            round_trip(Encoding::Cbor, &msg);
+        }
+    }
+
+    // `serde_bytes` keeps a hand at one byte per kind instead of an integer array
+    #[test]
+    fn cards_are_bytes() {
+        let msg = ServerMessage::CardSync(Cards::default());
+        let json = Encoding::Json.encode(&msg).unwrap();
+        for encoding in [Encoding::Msgpack, Encoding::Cbor] {
+            let bytes = encoding.encode(&msg).unwrap();
+            assert!(bytes.len() < json.len());
+            assert!(bytes.windows(34).any(|w| w == [0; 34]));
+        }
+    }
+
+    // a newer client may prefer encodings this server has never heard of
+    #[test]
+    fn hello_skips_unknown_encodings() {
+        let hello = r#"{"tag":"Hello","content":{"version":2,"capabilities":[],"encodings":["zstd","cbor","json"]}}"#;
+        let Ok(ClientMessage::Hello { encodings, .. }) = serde_json::from_str(hello) else {
+            panic!("hello rejected");
+        };
+        assert_eq!(encodings, vec![Encoding::Cbor, Encoding::Json]);
+
+        let hello =
+            r#"{"tag":"Hello","content":{"version":2,"capabilities":[],"encodings":["zstd"]}}"#;
+        let Ok(ClientMessage::Hello { encodings, .. }) = serde_json::from_str(hello) else {
+            panic!("hello rejected");
+        };
+        assert_eq!(
+            encodings.first().copied().unwrap_or_default(),
+            Encoding::Json
+        );
+    }
+
+    // a request that does not parse still gives its id back
+    #[test]
+    fn request_id_survives_bad_message() {
+        #[derive(Serialize)]
+        struct Unknown {
+            id: u64,
+            tag: &'static str,
+        }
+        let msg = Unknown { id: 9, tag: "Nope" };
+        for encoding in [Encoding::Json, Encoding::Msgpack, Encoding::Cbor] {
+            let bytes = encoding.encode(&msg).unwrap();
+            assert!(encoding.decode::<ClientRequest>(&bytes).is_err());
+            let id: RequestId = encoding.decode(&bytes).unwrap();
+            assert_eq!(id.id, Some(9));
+        }
+    }
+}